@@ -0,0 +1,17 @@
+//! Captures the git revision at build time
+
+use std::process::Command;
+
+fn main() {
+    // Get the abbreviated commit hash if we are building from a git checkout
+    let output = Command::new("git").args(["rev-parse", "--short", "HEAD"]).output();
+    let git_hash = match output {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        _ => String::from("unknown"),
+    };
+
+    // Export the hash and rebuild if the checked-out commit changes
+    println!("cargo:rustc-env=RTSP2HLS_GIT_HASH={git_hash}");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
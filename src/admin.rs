@@ -0,0 +1,190 @@
+//! Administrative request handlers
+
+use crate::config::Config;
+use crate::hls;
+use crate::sources;
+use crate::state::State;
+use crate::stats;
+use ehttpd::http::{Request, RequestExt, Response, ResponseExt};
+
+/// Handles a POST request for `/admin/purge`
+///
+/// # Note
+/// This deletes the current playlist and all fragments immediately, while the workers keep running; unlike a reload,
+/// the RTSP sessions are not restarted. As `hlssink` keeps listing the deleted fragments until they have dropped out of
+/// its playlist, the playlist route reports that the stream is warming up until the buffer has been refilled.
+pub fn post_purge(request: &Request, config: &Config, state: &State) -> Response {
+    // Authorize the request
+    if let Err(response) = authorize(request, config) {
+        return response;
+    }
+
+    // Purge all HLS artifacts of all streams and let the running workers refill the buffer
+    for source in sources::current(config, state) {
+        if let Err(e) = hls::remove_artifacts(&source, state) {
+            e.log_to_stderr();
+            return Response::new_500_internalservererror();
+        }
+        state.rings.clear(&source.name);
+    }
+    Response::new_status_reason(204, "No Content")
+}
+
+/// Handles a POST request for `/admin/reload`
+///
+/// # Note
+/// This asks the watchdog to restart the workers of all streams with a new RTSP session and an empty buffer, e.g. after
+/// the camera settings have changed. The restart is performed asynchronously, so the response does not wait for it.
+pub fn post_reload(request: &Request, config: &Config, state: &State) -> Response {
+    // Authorize the request
+    if let Err(response) = authorize(request, config) {
+        return response;
+    }
+
+    // Request the restart of all streams
+    for source in sources::current(config, state) {
+        state.supervision.request_reload(&source.name);
+    }
+    Response::new_status_reason(202, "Accepted")
+}
+
+/// Handles a GET request for `/admin/config`
+///
+/// # Note
+/// This serializes the effective config as JSON object with the environment variable names as keys and the formatted
+/// values as strings, or `null` if unset; secrets are redacted.
+pub fn get_config(request: &Request, config: &Config) -> Response {
+    // Authorize the request
+    if let Err(response) = authorize(request, config) {
+        return response;
+    }
+
+    // Serialize the effective config
+    let values: Vec<String> = (config.effective().into_iter())
+        .map(|(name, value)| match value {
+            Some(value) => format!(r#""{name}":"{}""#, stats::escape_json(&value)),
+            None => format!(r#""{name}":null"#),
+        })
+        .collect();
+
+    // Assemble response
+    let mut response = Response::new_200_ok();
+    response.set_body_data(format!("{{{}}}", values.join(",")));
+    response.set_content_type("application/json");
+    response.set_field("Cache-Control", "no-store");
+    response
+}
+
+/// Ensures that the request carries the configured admin bearer token
+#[allow(clippy::result_large_err, reason = "The error is the rejection response that is sent as-is")]
+fn authorize(request: &Request, config: &Config) -> Result<(), Response> {
+    // Administrative endpoints are disabled if no token is configured
+    let Some(token) = &config.RTSP2HLS_ADMINTOKEN else {
+        return Err(Response::new_404_notfound());
+    };
+
+    // Validate the authorization header
+    let expected = format!("Bearer {token}");
+    let Some(authorization) = request.field("Authorization") else {
+        let mut response = Response::new_status_reason(401, "Unauthorized");
+        response.set_field("WWW-Authenticate", "Bearer");
+        return Err(response);
+    };
+    let true = constant_time_eq(authorization.as_ref(), expected.as_bytes()) else {
+        return Err(Response::new_403_forbidden());
+    };
+    Ok(())
+}
+
+/// Compares two byte strings in constant time with respect to their contents
+fn constant_time_eq(lhs: &[u8], rhs: &[u8]) -> bool {
+    let true = lhs.len() == rhs.len() else {
+        return false;
+    };
+    let difference = lhs.iter().zip(rhs).fold(0, |difference, (lhs, rhs)| difference | (lhs ^ rhs));
+    difference == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{constant_time_eq, post_purge};
+    use crate::config::{Config, Container, Source};
+    use crate::error;
+    use crate::error::Error;
+    use crate::hls;
+    use crate::state::State;
+    use ehttpd::bytes;
+    use ehttpd::http::{Request, Response};
+    use std::borrow::Cow;
+    use std::fs;
+    use std::io::{BufReader, Cursor};
+
+    /// Requests the given target with the given method and the test admin token, and returns the response status
+    fn status(method: &str, target: &str, handler: impl Fn(&Request) -> Response) -> Result<Vec<u8>, Error> {
+        let head = format!("{method} {target} HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer token\r\n\r\n");
+        let mut stream = bytes::Source::new(BufReader::new(Cursor::new(head.into_bytes())));
+        let request = Request::from_stream(&mut stream)?.ok_or_else(|| error!("Missing test request"))?;
+        Ok(handler(&request).status.to_vec())
+    }
+
+    /// A purge removes the fragments without restarting the worker, and the stream is warming up until the running
+    /// worker has refilled its playlist with new fragments
+    #[test]
+    fn post_purge_refill() -> Result<(), Error> {
+        let (mut config, state) = (Config::for_tests()?, State::default());
+        let source = Source::for_tests("purge");
+        (config.RTSP2HLS_ADMINTOKEN, config.RTSP2HLS_SOURCES) = (Some(Cow::Borrowed("token")), vec![source.clone()]);
+        (config.RTSP2HLS_CONTAINER, config.RTSP2HLS_MEMORY) = (Container::MpegTs, false);
+        let playlist = |numbers: [u32; 2]| {
+            let segments = numbers.map(|number| format!("#EXTINF:1.0,\nlive-{number:08}.ts\n"));
+            format!("#EXTM3U\n#EXT-X-MEDIA-SEQUENCE:{}\n{}", numbers[0], segments.concat())
+        };
+        let index = |request: &Request| hls::get_index(request, b"", &source, &config, &state);
+        let fragment = |request: &Request| hls::get_fragment(request, None, &source, &config, &state);
+
+        // Serve the stream
+        fs::create_dir_all(&source.directory)?;
+        for number in [4, 5] {
+            fs::write(source.directory.join(format!("live-{number:08}.ts")), b"fragment")?;
+        }
+        fs::write(source.playlist(), playlist([4, 5]))?;
+        assert_eq!(status("GET", "/index.m3u8", index)?, b"200");
+        assert_eq!(status("GET", "/live-00000005.ts", fragment)?, b"200");
+
+        // Purge the stream
+        assert_eq!(status("POST", "/admin/purge", |request| post_purge(request, &config, &state))?, b"204");
+        assert!(!source.directory.join("live-00000005.ts").exists() && !source.playlist().exists());
+        assert_eq!(status("GET", "/index.m3u8", index)?, b"503");
+
+        // The running worker still lists a purged fragment
+        fs::write(source.directory.join("live-00000006.ts"), b"fragment")?;
+        fs::write(source.playlist(), playlist([5, 6]))?;
+        assert_eq!(status("GET", "/index.m3u8", index)?, b"503");
+
+        // The running worker has refilled its playlist
+        fs::write(source.directory.join("live-00000007.ts"), b"fragment")?;
+        fs::write(source.playlist(), playlist([6, 7]))?;
+        assert_eq!(status("GET", "/index.m3u8", index)?, b"200");
+        assert_eq!(status("GET", "/live-00000007.ts", fragment)?, b"200");
+
+        fs::remove_dir_all(&source.directory)?;
+        Ok(())
+    }
+
+    /// Equal byte strings compare equal, including the empty string
+    #[test]
+    fn constant_time_eq_equal() {
+        assert!(constant_time_eq(b"secret-token", b"secret-token"));
+        assert!(constant_time_eq(b"", b""));
+    }
+
+    /// Byte strings that differ in any position or in length compare unequal
+    #[test]
+    fn constant_time_eq_different() {
+        assert!(!constant_time_eq(b"secret-token", b"secret-tokem"));
+        assert!(!constant_time_eq(b"secret-token", b"Secret-token"));
+        assert!(!constant_time_eq(b"secret-token", b"secret-token2"));
+        assert!(!constant_time_eq(b"secret-token", b"secret"));
+        assert!(!constant_time_eq(b"secret-token", b""));
+    }
+}
@@ -0,0 +1,164 @@
+//! Request handlers for static assets and common client probes
+
+use crate::config::{Config, Source};
+use crate::sources;
+use crate::state::State;
+use ehttpd::http::{Response, ResponseExt};
+
+/// A minimal permissive cross-domain policy for legacy Flash-based players
+const CROSSDOMAIN_XML: &str = concat!(
+    r#"<?xml version="1.0"?>"#,
+    "\n",
+    r#"<cross-domain-policy><allow-access-from domain="*"/></cross-domain-policy>"#,
+    "\n"
+);
+
+/// The portal page template; `{streams}` is replaced with the list items of the available streams
+const PORTAL_HTML: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>rtsp2hls</title>
+</head>
+<body>
+<h1>Available streams</h1>
+<ul>
+{streams}</ul>
+</body>
+</html>
+"#;
+
+/// The player page template; `{name}` is replaced with the stream name and `{src}` with the playlist URL
+///
+/// # Note
+/// Browsers with native HLS support (e.g. Safari) play the stream directly, all other browsers use `hls.js` from a CDN.
+const PLAYER_HTML: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>rtsp2hls: {name}</title>
+<script src="https://cdn.jsdelivr.net/npm/hls.js@1"></script>
+</head>
+<body>
+<video id="player" data-src="{src}" controls autoplay muted playsinline style="width: 100%"></video>
+<script>
+const video = document.getElementById("player");
+if (video.canPlayType("application/vnd.apple.mpegurl")) {
+    video.src = video.dataset.src;
+} else if (window.Hls && Hls.isSupported()) {
+    const hls = new Hls();
+    hls.loadSource(video.dataset.src);
+    hls.attachMedia(video);
+}
+</script>
+</body>
+</html>
+"#;
+
+/// Handles a GET request for `/crossdomain.xml`
+pub fn get_crossdomain() -> Response {
+    let mut response = Response::new_200_ok();
+    response.set_body_data(CROSSDOMAIN_XML);
+    response.set_content_type("text/x-cross-domain-policy");
+    response
+}
+
+/// Handles a GET request for `/favicon.ico`
+pub fn get_favicon() -> Response {
+    // We don't have a favicon, but we don't want browsers to treat it as error either
+    Response::new_status_reason(204, "No Content")
+}
+
+/// Handles a GET request for `/index.html`
+pub fn get_portal(config: &Config, state: &State) -> Response {
+    // The portal is disabled by default
+    let true = config.RTSP2HLS_PORTAL else {
+        return Response::new_404_notfound();
+    };
+
+    // Render the stream list
+    let streams: String = (sources::current(config, state).iter())
+        .map(|source| (&source.name, source.playlist_url(&config.RTSP2HLS_PATHPREFIX)))
+        .map(|(name, url)| format!("<li><a href=\"{}\">{}</a></li>\n", escape_html(&url), escape_html(name)))
+        .collect();
+
+    // Assemble response
+    let mut response = Response::new_200_ok();
+    response.set_body_data(PORTAL_HTML.replace("{streams}", &streams));
+    response.set_content_type("text/html; charset=utf-8");
+    response
+}
+
+/// Handles a GET request for `/player.html` of a stream
+pub fn get_player(source: &Source, config: &Config) -> Response {
+    // Render the player for the stream
+    let src = source.playlist_url(&config.RTSP2HLS_PATHPREFIX);
+    let player = PLAYER_HTML.replace("{name}", &escape_html(&source.name)).replace("{src}", &escape_html(&src));
+
+    // Assemble response
+    let mut response = Response::new_200_ok();
+    response.set_body_data(player);
+    response.set_content_type("text/html; charset=utf-8");
+    response
+}
+
+/// Escapes a string for use within HTML text or attribute values
+fn escape_html(string: &str) -> String {
+    let mut escaped = String::with_capacity(string.len());
+    for char in string.chars() {
+        match char {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            char => escaped.push(char),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape_html, get_portal};
+    use crate::config::{Config, Source};
+    use crate::error::Error;
+    use crate::state::State;
+    use std::borrow::Cow;
+    use std::io::Read;
+
+    /// The portal links the playlist of each stream, and escapes the stream names
+    #[test]
+    fn get_portal_links() -> Result<(), Error> {
+        let mut config = Config::for_tests()?;
+        (config.RTSP2HLS_PORTAL, config.RTSP2HLS_PATHPREFIX) = (true, Cow::Borrowed("/prefix"));
+        config.RTSP2HLS_SOURCES =
+            vec![Source::for_tests("front"), Source { path: String::from("/back"), ..Source::for_tests("<back>") }];
+
+        let mut body = String::new();
+        get_portal(&config, &State::default()).body.read_to_string(&mut body)?;
+        assert!(body.contains("<li><a href=\"/prefix/front/index.m3u8\">front</a></li>\n"));
+        assert!(body.contains("<li><a href=\"/prefix/back/index.m3u8\">&lt;back&gt;</a></li>\n"));
+        Ok(())
+    }
+
+    /// The portal is not found if it is disabled
+    #[test]
+    fn get_portal_disabled() -> Result<(), Error> {
+        let mut config = Config::for_tests()?;
+        config.RTSP2HLS_PORTAL = false;
+        assert_eq!(get_portal(&config, &State::default()).status.as_ref(), b"404");
+        Ok(())
+    }
+
+    /// HTML special characters are escaped
+    #[test]
+    fn escape_html_special() {
+        assert_eq!(
+            escape_html(r#"<a href="x">Tom & Jerry's</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;"
+        );
+    }
+}
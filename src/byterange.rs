@@ -0,0 +1,186 @@
+//! Byte-range playlists over a single growing media file
+
+use crate::config::Source;
+use crate::error::Error;
+use crate::playlist::Playlist;
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::{Mutex, PoisonError};
+use std::time::SystemTime;
+
+/// The file name of the single media file within the stream directory
+pub const MEDIA_FILE: &str = "stream.ts";
+/// The media file length after which no more fragments are appended until the media file is restarted
+const MAX_LENGTH: u64 = 64 * 1024 * 1024;
+
+/// The media file index per stream directory
+#[derive(Debug, Default)]
+pub struct Indexes(Mutex<BTreeMap<PathBuf, Index>>);
+impl Indexes {
+    /// Appends all new segments of the given playlist to the media file of the given stream
+    ///
+    /// # Note
+    /// If the media file does not match the index (e.g. because the HLS artifacts have been removed), it is recreated
+    /// from the current segments. Once the media file is full, no more segments are appended, so that they are served
+    /// as discrete fragments; as soon as all appended segments have dropped out of the playlist, the media file is
+    /// restarted from scratch.
+    pub fn append(&self, source: &Source, playlist: &Playlist) -> Result<(), Error> {
+        // Take a copy of the index, so that the file I/O does not block the playlist requests
+        // Note: Only the watchdog appends to the media files, so the index cannot be changed concurrently
+        let mut index = {
+            let indexes = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+            indexes.get(&source.directory).cloned().unwrap_or_default()
+        };
+
+        // Forget all fragments that have dropped out of the playlist
+        index
+            .ranges
+            .retain(|name, _| playlist.segments.iter().any(|segment| segment.uri.trim_start_matches('/') == name));
+
+        // Recreate the media file if it does not match the index, or restart it once it is full and not referenced
+        let path = source.directory.join(MEDIA_FILE);
+        let length = match fs::metadata(&path) {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == ErrorKind::NotFound => 0,
+            Err(e) => return Err(e.into()),
+        };
+        if length != index.length || !path.is_file() || (index.full && index.ranges.is_empty()) {
+            index = Index::default();
+            File::create(&path)?;
+        }
+
+        // Append all new fragments until the media file is full
+        let mut file = OpenOptions::new().append(true).open(&path)?;
+        for segment in &playlist.segments {
+            // Skip fragments that have already been appended
+            let name = segment.uri.trim_start_matches('/');
+            if index.full || name.contains(['/', '\\']) {
+                continue;
+            }
+            let fragment = source.directory.join(name);
+            let Ok(modified) = fs::metadata(&fragment).and_then(|metadata| metadata.modified()) else {
+                // The fragment has been removed concurrently, so it cannot be served anyway
+                continue;
+            };
+            if index.ranges.get(name).is_some_and(|(appended, _)| *appended == modified) {
+                continue;
+            }
+
+            // Append the fragment
+            let fragment = fs::read(fragment)?;
+            file.write_all(&fragment)?;
+            let end = index.length.saturating_add(fragment.len() as u64);
+            index.ranges.insert(name.to_string(), (modified, index.length..end));
+            (index.length, index.full) = (end, end >= MAX_LENGTH);
+        }
+
+        // Publish the updated index
+        let mut indexes = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        indexes.insert(source.directory.clone(), index);
+        Ok(())
+    }
+
+    /// Returns the byte range of each appended segment within the media file of the given stream
+    pub fn ranges(&self, source: &Source) -> BTreeMap<String, Range<u64>> {
+        let indexes = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(index) = indexes.get(&source.directory) else {
+            return BTreeMap::new();
+        };
+        index.ranges.iter().map(|(name, (_, range))| (name.clone(), range.clone())).collect()
+    }
+
+    /// Stops appending to the media file of the given stream, so that it is restarted once all of its segments have
+    /// dropped out of the playlist
+    pub fn restart(&self, source: &Source) {
+        let mut indexes = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(index) = indexes.get_mut(&source.directory) {
+            index.full = true;
+        }
+    }
+}
+
+/// The index of a media file
+#[derive(Debug, Clone, Default)]
+struct Index {
+    /// The expected length of the media file
+    length: u64,
+    /// The modification time and the byte range within the media file per appended fragment
+    ranges: BTreeMap<String, (SystemTime, Range<u64>)>,
+    /// Whether the media file is full, i.e. whether it is restarted once all appended fragments have dropped out
+    full: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Indexes;
+    use crate::config::Source;
+    use crate::error::Error;
+    use crate::playlist::Playlist;
+    use std::fs;
+    use std::ops::Range;
+
+    /// Creates a test source with the given name within its own directory that contains the given fragments
+    fn stream(name: &str, fragments: &[(&str, &str)]) -> Result<Source, Error> {
+        let source = Source::for_tests(name);
+        fs::create_dir_all(&source.directory)?;
+        for (fragment, contents) in fragments {
+            fs::write(source.directory.join(fragment), contents)?;
+        }
+        Ok(source)
+    }
+
+    /// Creates a playlist with the given fragments
+    fn playlist(fragments: &[&str]) -> Playlist {
+        let segments: String = fragments.iter().map(|fragment| format!("#EXTINF:1.0,\n{fragment}\n")).collect();
+        Playlist::parse(&format!("#EXTM3U\n#EXT-X-TARGETDURATION:1\n{segments}"))
+    }
+
+    /// Returns the byte ranges of the given stream as sorted list
+    fn ranges(indexes: &Indexes, source: &Source) -> Vec<(String, Range<u64>)> {
+        indexes.ranges(source).into_iter().collect()
+    }
+
+    /// New fragments are appended, and fragments that have dropped out of the playlist are forgotten
+    #[test]
+    fn append_ranges() -> Result<(), Error> {
+        let source = stream("append-ranges", &[("live-0.ts", "abc"), ("live-1.ts", "defg"), ("live-2.ts", "hi")])?;
+        let indexes = Indexes::default();
+
+        indexes.append(&source, &playlist(&["live-0.ts", "live-1.ts"]))?;
+        let expected = [(String::from("live-0.ts"), 0..3), (String::from("live-1.ts"), 3..7)];
+        assert_eq!(ranges(&indexes, &source), expected);
+
+        indexes.append(&source, &playlist(&["live-1.ts", "live-2.ts"]))?;
+        let expected = [(String::from("live-1.ts"), 3..7), (String::from("live-2.ts"), 7..9)];
+        assert_eq!(ranges(&indexes, &source), expected);
+        assert_eq!(fs::read_to_string(source.directory.join(super::MEDIA_FILE))?, "abcdefghi");
+
+        fs::remove_dir_all(&source.directory)?;
+        Ok(())
+    }
+
+    /// A restarted media file is not appended to until all of its fragments have dropped out of the playlist
+    #[test]
+    fn append_restart() -> Result<(), Error> {
+        let source = stream("append-restart", &[("live-0.ts", "abc"), ("live-1.ts", "defg"), ("live-2.ts", "hi")])?;
+        let indexes = Indexes::default();
+        indexes.append(&source, &playlist(&["live-0.ts"]))?;
+
+        // The new fragment is not appended while the old one is still referenced
+        indexes.restart(&source);
+        indexes.append(&source, &playlist(&["live-0.ts", "live-1.ts"]))?;
+        assert_eq!(ranges(&indexes, &source), [(String::from("live-0.ts"), 0..3)]);
+
+        // The media file starts from scratch once the old fragment has dropped out
+        indexes.append(&source, &playlist(&["live-1.ts", "live-2.ts"]))?;
+        let expected = [(String::from("live-1.ts"), 0..4), (String::from("live-2.ts"), 4..6)];
+        assert_eq!(ranges(&indexes, &source), expected);
+        assert_eq!(fs::read_to_string(source.directory.join(super::MEDIA_FILE))?, "defghi");
+
+        fs::remove_dir_all(&source.directory)?;
+        Ok(())
+    }
+}
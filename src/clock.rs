@@ -0,0 +1,133 @@
+//! Wall-clock jump detection
+
+use crate::error;
+use std::time::{Duration, Instant, SystemTime};
+
+/// A source of the current monotonic and wall-clock time
+pub trait Clock {
+    /// Gets the current monotonic and wall-clock time
+    fn now(&self) -> (Instant, SystemTime);
+}
+
+/// The system clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+impl Clock for SystemClock {
+    fn now(&self) -> (Instant, SystemTime) {
+        (Instant::now(), SystemTime::now())
+    }
+}
+
+/// A monitor to detect wall-clock jumps (e.g. NTP steps) by comparing the wall clock against a monotonic reference
+#[derive(Debug)]
+pub struct ClockMonitor<C = SystemClock> {
+    /// The clock to monitor
+    clock: C,
+    /// The maximum tolerated divergence between the wall clock and the monotonic clock
+    threshold: Duration,
+    /// The monotonic reference timestamp
+    monotonic: Instant,
+    /// The wall-clock reference timestamp
+    wallclock: SystemTime,
+}
+impl ClockMonitor {
+    /// Creates a new monitor of the system clock with the given threshold
+    pub fn new(threshold: Duration) -> Self {
+        Self::with_clock(SystemClock, threshold)
+    }
+}
+impl<C> ClockMonitor<C>
+where
+    C: Clock,
+{
+    /// Creates a new monitor of the given clock with the given threshold
+    pub fn with_clock(clock: C, threshold: Duration) -> Self {
+        let (monotonic, wallclock) = clock.now();
+        Self { clock, threshold, monotonic, wallclock }
+    }
+
+    /// Compares the elapsed wall-clock time against the elapsed monotonic time since the last check, and returns
+    /// whether a wall-clock jump has been detected
+    ///
+    /// # Note
+    /// If a jump is detected, the skew state should be kept until the next check, which gives all fragments written
+    /// before the jump time to rotate out.
+    pub fn check(&mut self) -> bool {
+        // Get the elapsed times since the last check
+        let (monotonic, wallclock) = self.clock.now();
+        let monotonic_elapsed = monotonic.saturating_duration_since(self.monotonic);
+        let divergence = match wallclock.duration_since(self.wallclock) {
+            Ok(wallclock_elapsed) => wallclock_elapsed.abs_diff(monotonic_elapsed),
+            Err(e) => e.duration().saturating_add(monotonic_elapsed),
+        };
+
+        // Update the skew state and the references
+        let skewed = divergence > self.threshold;
+        if skewed {
+            let seconds = divergence.as_secs_f64();
+            error!("Detected a wall-clock jump of {seconds:.3}s; ignoring file modification times").log_to_stderr();
+        }
+        (self.monotonic, self.wallclock) = (monotonic, wallclock);
+        skewed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Clock, ClockMonitor};
+    use std::cell::Cell;
+    use std::time::{Duration, Instant, SystemTime};
+
+    /// A manually advanced clock
+    struct ManualClock(Cell<(Instant, SystemTime)>);
+    impl ManualClock {
+        /// Advances the monotonic clock by the given duration and updates the wall clock with the given function
+        fn advance(&self, elapsed: Duration, wallclock: impl FnOnce(SystemTime) -> Option<SystemTime>) {
+            let (monotonic, current) = self.0.get();
+            let monotonic = monotonic.checked_add(elapsed).unwrap_or(monotonic);
+            self.0.set((monotonic, wallclock(current).unwrap_or(current)));
+        }
+    }
+    impl Clock for ManualClock {
+        fn now(&self) -> (Instant, SystemTime) {
+            self.0.get()
+        }
+    }
+
+    /// Creates a monitor of a manual clock with a threshold of 5 seconds
+    fn monitor() -> ClockMonitor<ManualClock> {
+        let clock = ManualClock(Cell::new((Instant::now(), SystemTime::now())));
+        ClockMonitor::with_clock(clock, Duration::from_secs(5))
+    }
+
+    /// Wall-clock time that elapses in step with the monotonic time, including a tolerated drift, is not a jump
+    #[test]
+    fn check_steady() {
+        let mut monitor = monitor();
+        let elapsed = Duration::from_secs(10);
+        monitor.clock.advance(elapsed, |now| now.checked_add(elapsed));
+        assert!(!monitor.check());
+        monitor.clock.advance(elapsed, |now| now.checked_add(elapsed + Duration::from_secs(2)));
+        assert!(!monitor.check());
+    }
+
+    /// A backward wall-clock jump is detected once and the next regular check is not skewed anymore
+    #[test]
+    fn check_backward_jump() {
+        let mut monitor = monitor();
+        let elapsed = Duration::from_secs(10);
+        monitor.clock.advance(elapsed, |now| now.checked_sub(Duration::from_secs(60)));
+        assert!(monitor.check());
+        monitor.clock.advance(elapsed, |now| now.checked_add(elapsed));
+        assert!(!monitor.check());
+    }
+
+    /// A forward wall-clock jump beyond the threshold is detected
+    #[test]
+    fn check_forward_jump() {
+        let mut monitor = monitor();
+        let elapsed = Duration::from_secs(10);
+        monitor.clock.advance(elapsed, |now| now.checked_add(elapsed + Duration::from_secs(60)));
+        assert!(monitor.check());
+    }
+}
@@ -0,0 +1,89 @@
+//! Free disk space monitoring of the temp directory
+
+use crate::error;
+use crate::error::Error;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+/// A monitor to detect if the filesystem of the temp directory is running full
+#[derive(Debug)]
+pub struct DiskMonitor {
+    /// The monitored directory
+    directory: PathBuf,
+    /// The minimum amount of available bytes, or `0` to disable the monitor
+    threshold: u64,
+    /// Whether the filesystem has been critically low on space during the last check
+    low: bool,
+}
+impl DiskMonitor {
+    /// Creates a new disk monitor for the given directory and threshold
+    pub fn new(directory: &Path, threshold: u64) -> Self {
+        Self { directory: directory.to_path_buf(), threshold, low: false }
+    }
+
+    /// Compares the available space on the filesystem against the threshold, and returns whether the filesystem is
+    /// critically low on space
+    ///
+    /// # Note
+    /// The low-space state is only logged if it changes, so that a full filesystem does not flood the log. If the space
+    /// cannot be queried, the previous state is kept.
+    pub fn check(&mut self) -> bool {
+        // Get the available space
+        if self.threshold == 0 {
+            return false;
+        }
+        let available = match available(&self.directory) {
+            Ok(available) => available,
+            Err(e) => {
+                let directory = self.directory.display();
+                error!("Failed to get the free space of {directory}: {}", e.error).log_to_stderr();
+                return self.low;
+            }
+        };
+
+        // Update the low-space state
+        let low = available < self.threshold;
+        match (low, mem::replace(&mut self.low, low)) {
+            (true, false) => {
+                let (directory, threshold) = (self.directory.display(), self.threshold);
+                let message = "The filesystem of the temp directory is critically low on space";
+                error!("{message} ({directory}: {available} bytes available, {threshold} bytes required)")
+                    .log_to_stderr();
+            }
+            (false, true) => eprintln!("The filesystem of the temp directory has enough space again"),
+            _ => (),
+        }
+        low
+    }
+}
+
+/// Gets the amount of bytes available to unprivileged users on the filesystem of the given path
+#[cfg(unix)]
+fn available(path: &Path) -> Result<u64, Error> {
+    use std::ffi::CString;
+    use std::io;
+    use std::mem::MaybeUninit;
+    use std::os::unix::ffi::OsStrExt;
+
+    // Query the filesystem statistics
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: The path is a valid NUL-terminated string and `stat` is a valid pointer to a `statvfs` struct
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    // SAFETY: The struct has been initialized by the successful call
+    let stat = unsafe { stat.assume_init() };
+    #[allow(clippy::useless_conversion, reason = "The field types differ between platforms")]
+    let (blocks, block_size) = (u64::from(stat.f_bavail), u64::from(stat.f_frsize));
+    Ok(blocks.saturating_mul(block_size))
+}
+/// Gets the amount of bytes available on the filesystem of the given path
+///
+/// # Note
+/// This is not supported on non-Unix platforms, so the filesystem is always considered to have enough space.
+#[cfg(not(unix))]
+fn available(_path: &Path) -> Result<u64, Error> {
+    Ok(u64::MAX)
+}
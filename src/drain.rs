@@ -0,0 +1,37 @@
+//! Graceful draining of the HTTP server on `SIGUSR1`, `SIGTERM` and `SIGINT`
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether the server is draining
+///
+/// # Note
+/// Unlike the remaining runtime state, this cannot be part of the config, because the signal handler cannot capture it.
+static DRAINING: AtomicBool = AtomicBool::new(false);
+
+/// Installs the `SIGUSR1`, `SIGTERM` and `SIGINT` handlers that start draining
+///
+/// # Note
+/// This is only supported on Unix; on other platforms, the server never drains. After the first `SIGTERM` or `SIGINT`,
+/// the default handler is restored, so that a repeated signal terminates the process immediately.
+pub fn install() {
+    /// Starts draining
+    #[cfg(unix)]
+    extern "C" fn on_signal(signal: libc::c_int) {
+        DRAINING.store(true, Ordering::SeqCst);
+        if signal != libc::SIGUSR1 {
+            // SAFETY: Restoring the default handler is async-signal-safe
+            unsafe { libc::signal(signal, libc::SIG_DFL) };
+        }
+    }
+
+    #[cfg(unix)]
+    for signal in [libc::SIGUSR1, libc::SIGTERM, libc::SIGINT] {
+        // SAFETY: The handler only stores into an atomic and restores the default handler, which is async-signal-safe
+        unsafe { libc::signal(signal, on_signal as *const () as libc::sighandler_t) };
+    }
+}
+
+/// Whether the server is draining, i.e. it finishes the in-flight requests but does not accept new connections
+pub fn is_draining() -> bool {
+    DRAINING.load(Ordering::SeqCst)
+}
@@ -3,10 +3,19 @@
 use std::backtrace::{Backtrace, BacktraceStatus};
 use std::error;
 use std::fmt::{self, Display, Formatter};
+use std::io;
 
-/// Creates a new error
+/// Creates a new error, optionally with an explicit error kind like `error!(kind: Pipeline, "...")`
 #[macro_export]
 macro_rules! error {
+    (kind: $kind:ident, with: $error:expr, $($arg:tt)*) => {{
+        let error = $crate::error!(with: $error, $($arg)*);
+        error.with_kind($crate::error::ErrorKind::$kind)
+    }};
+    (kind: $kind:ident, $($arg:tt)*) => {{
+        let error = $crate::error!($($arg)*);
+        error.with_kind($crate::error::ErrorKind::$kind)
+    }};
     (with: $error:expr, $($arg:tt)*) => {{
         let error = format!($($arg)*);
         let source = Box::new($error);
@@ -18,9 +27,47 @@ macro_rules! error {
     }};
 }
 
+/// The failure category of an error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// An invalid configuration
+    Config,
+    /// An I/O error
+    Io,
+    /// A failure of the `gstreamer` pipeline or the RTSP source
+    Pipeline,
+    /// A `gstreamer` pipeline that has stopped producing fragments
+    Stalled,
+    /// A missing runtime dependency, e.g. the `gstreamer` binaries
+    Dependency,
+    /// A failure of the HTTP server, e.g. an unavailable listen address
+    Http,
+    /// Any other error
+    Other,
+}
+impl ErrorKind {
+    /// The process exit code for errors of this category
+    ///
+    /// # Note
+    /// A clean shutdown (e.g. after the idle timeout) exits with `0`.
+    pub const fn exit_code(self) -> i32 {
+        match self {
+            Self::Other => 1,
+            Self::Pipeline => 2,
+            Self::Stalled => 3,
+            Self::Config => 4,
+            Self::Http => 5,
+            Self::Dependency => 6,
+            Self::Io => 7,
+        }
+    }
+}
+
 /// The crates error type
 #[derive(Debug)]
 pub struct Error {
+    /// The failure category
+    pub kind: ErrorKind,
     /// The error description
     pub error: String,
     /// The underlying error
@@ -30,10 +77,24 @@ pub struct Error {
 }
 impl Error {
     /// Creates a new error
+    ///
+    /// # Note
+    /// Errors that are caused by an I/O error are categorized as [`ErrorKind::Io`], all other errors as
+    /// [`ErrorKind::Other`]; use [`Self::with_kind`] to set a more specific category.
     #[doc(hidden)]
     pub fn new(error: String, source: Option<Box<dyn error::Error + Send>>) -> Self {
+        let kind = match &source {
+            Some(source) if source.is::<io::Error>() => ErrorKind::Io,
+            _ => ErrorKind::Other,
+        };
         let backtrace = Backtrace::capture();
-        Self { error, source, backtrace }
+        Self { kind, error, source, backtrace }
+    }
+
+    /// Sets the failure category
+    pub fn with_kind(mut self, kind: ErrorKind) -> Self {
+        self.kind = kind;
+        self
     }
 
     /// Logs `self` to stderr
@@ -0,0 +1,294 @@
+//! HTTP header helpers for validators, dates, byte ranges and proxy headers
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs::Metadata;
+use std::hash::Hasher;
+use std::net::{IpAddr, SocketAddr};
+use std::ops::Range;
+use std::str;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The abbreviated weekday names, starting with thursday as `1970-01-01` was a thursday
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+/// The abbreviated month names
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// A byte range selection for a resource
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ByteRange {
+    /// The full resource
+    Full,
+    /// A partial range within the resource
+    Partial(Range<u64>),
+    /// A range that cannot be satisfied for the resource
+    Unsatisfiable,
+}
+
+/// Computes a strong entity tag for a file from its size and modification time
+pub fn etag(metadata: &Metadata) -> String {
+    let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+    let modified = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!(r#""{:x}-{:x}""#, metadata.len(), modified.as_nanos())
+}
+
+/// Computes a strong entity tag from the given content and its content coding if any
+///
+/// # Note
+/// The content coding is part of the entity tag, as each encoded representation of the content needs its own tag.
+pub fn content_etag(content: &[u8], encoding: Option<&str>) -> String {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(content);
+    match encoding {
+        Some(encoding) => format!(r#""{:x}-{encoding}""#, hasher.finish()),
+        None => format!(r#""{:x}""#, hasher.finish()),
+    }
+}
+
+/// Checks if an `If-None-Match`-header value matches the given entity tag using the weak comparison
+pub fn matches_etag(value: &[u8], etag: &str) -> bool {
+    let Ok(value) = str::from_utf8(value) else {
+        return false;
+    };
+
+    // Compare each listed entity tag without its weakness indicator
+    let etag = etag.trim_start_matches("W/");
+    value.split(',').map(str::trim).any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+}
+
+/// Checks if a resource with the given modification time is unmodified since an `If-Modified-Since`-header value
+///
+/// # Note
+/// As HTTP dates have a resolution of one second, the modification time is truncated to full seconds.
+pub fn is_unmodified_since(value: &[u8], modified: SystemTime) -> bool {
+    let Some(since) = parse_http_date(value) else {
+        return false;
+    };
+    let modified = modified.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let since = since.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    modified <= since
+}
+
+/// Parses the left-most client address from an `X-Forwarded-For`-header value, e.g. `203.0.113.7` from
+/// `203.0.113.7, 10.0.0.1`
+pub fn parse_forwarded_for(value: &[u8]) -> Option<IpAddr> {
+    let value = str::from_utf8(value).ok()?;
+    let (client, _proxies) = value.split_once(',').unwrap_or((value, ""));
+    parse_ip(client.as_bytes())
+}
+
+/// Parses an IP address with an optional port from an `X-Real-IP`- or `X-Forwarded-For`-header value, e.g.
+/// `203.0.113.7` or `[2001:db8::1]:443`
+pub fn parse_ip(value: &[u8]) -> Option<IpAddr> {
+    let value = str::from_utf8(value).ok()?.trim();
+    value.parse().ok().or_else(|| value.parse::<SocketAddr>().ok().map(|address| address.ip()))
+}
+
+/// Checks if an `Accept-Encoding`-header value accepts the given content coding
+pub fn accepts_encoding(value: &[u8], encoding: &str) -> bool {
+    let Ok(value) = str::from_utf8(value) else {
+        return false;
+    };
+
+    // Find the coding and ensure that it is not explicitly rejected via `q=0`
+    value.split(',').any(|candidate| {
+        let mut parameters = candidate.split(';').map(str::trim);
+        let coding = parameters.next().unwrap_or_default();
+        let rejected = parameters.any(|parameter| matches!(parameter, "q=0" | "q=0.0" | "q=0.00" | "q=0.000"));
+        coding.eq_ignore_ascii_case(encoding) && !rejected
+    })
+}
+
+/// Formats a timestamp as IMF-fixdate, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`
+///
+/// # Note
+/// Timestamps before the UNIX epoch are clamped to the epoch.
+#[allow(clippy::arithmetic_side_effects, reason = "The calendar arithmetic operates on bounded values")]
+#[allow(clippy::indexing_slicing, reason = "The indices are always within the fixed table bounds")]
+pub fn format_http_date(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let (days, seconds) = (seconds / 86400, seconds % 86400);
+    let (year, month, day) = civil_from_days(days);
+
+    let weekday = WEEKDAYS[(days % 7) as usize];
+    let month = MONTHS[(month - 1) as usize];
+    let (hours, minutes, seconds) = (seconds / 3600, (seconds % 3600) / 60, seconds % 60);
+    format!("{weekday}, {day:02} {month} {year:04} {hours:02}:{minutes:02}:{seconds:02} GMT")
+}
+
+/// Formats a timestamp as ISO 8601 date-time with millisecond precision, e.g. `1994-11-06T08:49:37.000Z`
+///
+/// # Note
+/// Timestamps before the UNIX epoch are clamped to the epoch.
+#[allow(clippy::arithmetic_side_effects, reason = "The calendar arithmetic operates on bounded values")]
+pub fn format_iso8601(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let (seconds, millis) = (since_epoch.as_secs(), since_epoch.subsec_millis());
+    let (days, seconds) = (seconds / 86400, seconds % 86400);
+    let (year, month, day) = civil_from_days(days);
+
+    let (hours, minutes, seconds) = (seconds / 3600, (seconds % 3600) / 60, seconds % 60);
+    format!("{year:04}-{month:02}-{day:02}T{hours:02}:{minutes:02}:{seconds:02}.{millis:03}Z")
+}
+
+/// Parses an IMF-fixdate timestamp, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`
+///
+/// # Note
+/// The obsolete RFC 850 and asctime formats are not supported.
+#[allow(clippy::arithmetic_side_effects, reason = "The calendar arithmetic operates on bounded values")]
+pub fn parse_http_date(value: &[u8]) -> Option<SystemTime> {
+    // Split the timestamp into its components
+    let value = str::from_utf8(value).ok()?;
+    let components: Vec<&str> = value.split(' ').collect();
+    let [weekday, day, month, year, time, "GMT"] = components.as_slice() else {
+        return None;
+    };
+    let time: Vec<&str> = time.split(':').collect();
+    let [hours, minutes, seconds] = time.as_slice() else {
+        return None;
+    };
+
+    // Parse the components
+    let weekday = weekday.strip_suffix(',')?;
+    let true = WEEKDAYS.contains(&weekday) else {
+        return None;
+    };
+    let month = MONTHS.iter().position(|name| name == month)? as u64 + 1;
+    let (day, year) = (parse_digits(day, 2)?, parse_digits(year, 4)?);
+    let (hours, minutes, seconds) = (parse_digits(hours, 2)?, parse_digits(minutes, 2)?, parse_digits(seconds, 2)?);
+    let (1..=31, 1970.., 0..=23, 0..=59, 0..=60) = (day, year, hours, minutes, seconds) else {
+        return None;
+    };
+
+    // Assemble the timestamp
+    let seconds = days_from_civil(year, month, day) * 86400 + hours * 3600 + minutes * 60 + seconds;
+    UNIX_EPOCH.checked_add(Duration::from_secs(seconds))
+}
+
+/// Parses a `Range`-header value for a resource with the given length, or returns `None` if the byte range is malformed
+///
+/// # Note
+/// Only single byte ranges are supported; multiple ranges or other range units select the full resource.
+pub fn parse_range(value: &[u8], length: u64) -> Option<ByteRange> {
+    // Extract the range specifier
+    let Some(value) = value.strip_prefix(b"bytes=") else {
+        return Some(ByteRange::Full);
+    };
+    let value = str::from_utf8(value).ok()?;
+    if value.contains(',') {
+        return Some(ByteRange::Full);
+    }
+    let (start, end) = value.trim().split_once('-')?;
+
+    // Parse the range
+    let range = match (parse_decimal(start), parse_decimal(end)) {
+        // A suffix range like `-500`
+        (None, Some(0)) if start.is_empty() => return Some(ByteRange::Unsatisfiable),
+        (None, Some(suffix)) if start.is_empty() => length.saturating_sub(suffix)..length,
+        // An open range like `500-`
+        (Some(start), None) if end.is_empty() => start..length,
+        // A closed range like `500-999`
+        (Some(start), Some(end)) if start <= end => start..end.saturating_add(1).min(length),
+        // A malformed range
+        _ => return None,
+    };
+
+    // Validate the range
+    match range.start < length {
+        true => Some(ByteRange::Partial(range)),
+        false => Some(ByteRange::Unsatisfiable),
+    }
+}
+
+/// Parses a non-empty decimal number without sign
+fn parse_decimal(digits: &str) -> Option<u64> {
+    let false = digits.is_empty() else {
+        return None;
+    };
+    let true = digits.bytes().all(|byte| byte.is_ascii_digit()) else {
+        return None;
+    };
+    digits.parse().ok()
+}
+
+/// Parses a fixed-width decimal number
+fn parse_digits(digits: &str, width: usize) -> Option<u64> {
+    let true = digits.len() == width else {
+        return None;
+    };
+    parse_decimal(digits)
+}
+
+/// Converts days since the UNIX epoch into a `(year, month, day)`-tuple
+///
+/// # Note
+/// See <https://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+#[allow(clippy::arithmetic_side_effects, reason = "The calendar arithmetic operates on bounded values")]
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let days = days + 719468;
+    let (era, day_of_era) = (days / 146097, days % 146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Converts a `(year, month, day)`-tuple after `1970-01-01` into days since the UNIX epoch
+///
+/// # Note
+/// See <https://howardhinnant.github.io/date_algorithms.html#days_from_civil>
+#[allow(clippy::arithmetic_side_effects, reason = "The calendar arithmetic operates on bounded values")]
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = year - u64::from(month <= 2);
+    let (era, year_of_era) = (year / 400, year % 400);
+    let month_index = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_range, ByteRange};
+
+    /// Closed, open and suffix ranges select the matching bytes and are clamped to the resource length
+    #[test]
+    fn parse_range_partial() {
+        assert_eq!(parse_range(b"bytes=0-499", 1000), Some(ByteRange::Partial(0..500)));
+        assert_eq!(parse_range(b"bytes=500-999", 1000), Some(ByteRange::Partial(500..1000)));
+        assert_eq!(parse_range(b"bytes=500-4999", 1000), Some(ByteRange::Partial(500..1000)));
+        assert_eq!(parse_range(b"bytes=500-", 1000), Some(ByteRange::Partial(500..1000)));
+        assert_eq!(parse_range(b"bytes=-300", 1000), Some(ByteRange::Partial(700..1000)));
+        assert_eq!(parse_range(b"bytes=-3000", 1000), Some(ByteRange::Partial(0..1000)));
+    }
+
+    /// Other range units and multiple ranges select the full resource
+    #[test]
+    fn parse_range_full() {
+        assert_eq!(parse_range(b"items=0-1", 1000), Some(ByteRange::Full));
+        assert_eq!(parse_range(b"bytes=0-1,5-9", 1000), Some(ByteRange::Full));
+    }
+
+    /// Ranges beyond the resource and empty suffix ranges cannot be satisfied
+    #[test]
+    fn parse_range_unsatisfiable() {
+        assert_eq!(parse_range(b"bytes=1000-", 1000), Some(ByteRange::Unsatisfiable));
+        assert_eq!(parse_range(b"bytes=1000-1999", 1000), Some(ByteRange::Unsatisfiable));
+        assert_eq!(parse_range(b"bytes=-0", 1000), Some(ByteRange::Unsatisfiable));
+        assert_eq!(parse_range(b"bytes=0-", 0), Some(ByteRange::Unsatisfiable));
+    }
+
+    /// Malformed ranges are rejected
+    #[test]
+    fn parse_range_malformed() {
+        assert_eq!(parse_range(b"bytes=", 1000), None);
+        assert_eq!(parse_range(b"bytes=-", 1000), None);
+        assert_eq!(parse_range(b"bytes=500", 1000), None);
+        assert_eq!(parse_range(b"bytes=999-500", 1000), None);
+        assert_eq!(parse_range(b"bytes=+1-2", 1000), None);
+        assert_eq!(parse_range(b"bytes=a-b", 1000), None);
+        assert_eq!(parse_range(b"bytes=\xff-1", 1000), None);
+    }
+}
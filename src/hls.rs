@@ -1,81 +1,1295 @@
 //! HLS request handlers to serve a filesystem-backed HLS stream
 
-use crate::config::Config;
-use ehttpd::http::{Request, Response, ResponseExt};
-use std::fs::File;
+use crate::byterange;
+use crate::config::{Config, Container, PlaylistType, Source};
+use crate::error;
+use crate::error::Error;
+use crate::headers::{self, ByteRange};
+use crate::playlist::{self, Playlist};
+use crate::rtsp::RtspClient;
+use crate::sanitize;
+use crate::snapshot;
+use crate::state::State;
+use crate::throttle::{PacedReader, Pacer};
+use crate::uring::FragmentReader;
+use ehttpd::bytes;
+use ehttpd::http::{Request, RequestExt, Response, ResponseExt};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::fs::{self, File};
+use std::io::{self, Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant, UNIX_EPOCH};
 
-/// Handles a GET request for `/index.m3u8`
-pub fn get_index(request: &Request, config: &Config) -> Response {
+/// The maximum time to hold a blocking playlist reload (three target durations as recommended by the HLS spec)
+const BLOCKING_RELOAD_TIMEOUT: Duration = Duration::from_secs(RtspClient::SEGMENT_LENGTH.as_secs() * 3);
+/// The polling interval while holding a blocking playlist reload
+const BLOCKING_RELOAD_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// The file name prefix of the fMP4 initialization segments written by `hlscmafsink`, e.g. `init00000.mp4`
+const INIT_SEGMENT_PREFIX: &str = "init";
+/// The file name extension of the fMP4 initialization segments including the leading dot
+const INIT_SEGMENT_EXTENSION: &str = ".mp4";
+
+/// The oldest fragment counter that has been listed in a playlist of the current pipeline per stream directory
+#[derive(Debug, Default)]
+pub struct Listed(Mutex<BTreeMap<PathBuf, u64>>);
+
+/// The stream directories whose HLS artifacts have been removed, but whose new playlist has not been read yet
+#[derive(Debug, Default)]
+pub struct Resets {
+    /// The generation of the pending reset per stream directory
+    directories: Mutex<BTreeMap<PathBuf, u64>>,
+    /// The generation counter
+    generation: AtomicU64,
+}
+impl Resets {
+    /// Marks the given stream directory as reset
+    ///
+    /// # Note
+    /// Each mark starts a new generation, so that a read that has started before the mark cannot clear it.
+    fn mark(&self, directory: &Path) {
+        let generation = self.generation.fetch_add(1, Ordering::Relaxed);
+        let mut directories = self.directories.lock().unwrap_or_else(PoisonError::into_inner);
+        directories.insert(directory.to_path_buf(), generation);
+    }
+
+    /// Returns the generation of the pending reset of the given stream directory if any
+    fn pending(&self, directory: &Path) -> Option<u64> {
+        let directories = self.directories.lock().unwrap_or_else(PoisonError::into_inner);
+        directories.get(directory).copied()
+    }
+
+    /// Clears the pending reset of the given stream directory if it is still of the given generation
+    fn clear(&self, directory: &Path, generation: u64) {
+        let mut directories = self.directories.lock().unwrap_or_else(PoisonError::into_inner);
+        if directories.get(directory) == Some(&generation) {
+            directories.remove(directory);
+        }
+    }
+}
+
+/// The amount of blocking playlist reloads in progress
+#[derive(Debug, Default)]
+pub struct BlockingReloads(AtomicUsize);
+impl BlockingReloads {
+    /// Reserves a blocking reload if less than the given amount of blocking reloads are in progress
+    fn try_reserve(&self, max_reloads: usize) -> Option<BlockingReload<'_>> {
+        let reserved = self.0.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |reloads| {
+            (reloads < max_reloads).then(|| reloads.saturating_add(1))
+        });
+        reserved.ok().map(|_| BlockingReload { reloads: self })
+    }
+}
+
+/// A reserved blocking playlist reload that is released on drop
+#[derive(Debug)]
+struct BlockingReload<'a> {
+    /// The blocking reloads the reservation belongs to
+    reloads: &'a BlockingReloads,
+}
+impl Drop for BlockingReload<'_> {
+    fn drop(&mut self) {
+        self.reloads.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Handles a GET request for the playlist, i.e. `/index.m3u8` by default
+///
+/// # Note
+/// If the query contains an `_HLS_msn` delivery directive, the request is held until the playlist contains the
+/// requested media sequence number (see RFC 8216bis, section 6.2.5.2). As a held request occupies a worker, at most
+/// half of the workers hold blocking reloads at the same time; further blocking reloads are answered immediately.
+pub fn get_index(request: &Request, query: &[u8], source: &Source, config: &Config, state: &State) -> Response {
     // Assert request target as this route is fixed
-    assert_eq!(request.target, b"/index.m3u8", "invalid route");
+    assert_eq!(request.target.strip_prefix(b"/"), Some(source.playlist_name.as_bytes()), "invalid route");
+    state.stats.record_playlist_request();
 
-    // Open the index file
-    let path = config.RTSP2HLS_TEMPDIR.join("index.m3u8");
-    let Ok(file) = File::open(path) else {
-        // We cannot open the index file
-        return Response::new_404_notfound();
+    // Perform a blocking playlist reload if requested
+    match parse_delivery_directives(query) {
+        Ok(Some(media_sequence)) => {
+            let max_workers = config.RTSP2HLS_WORKERS.unwrap_or(config.RTSP2HLS_MAXCONN);
+            if let Some(_reload) = state.blocking_reloads.try_reserve(max_workers.div_ceil(2)) {
+                if let Err(response) = await_media_sequence(media_sequence, source, config, state) {
+                    return response;
+                }
+            }
+        }
+        Ok(None) => (),
+        Err(response) => return response,
+    }
+
+    // Read the index file
+    let playlist = match (read_playlist(source, config, state), state.supervision.is_unreachable(&source.name)) {
+        (Ok(playlist), _) => playlist,
+        // The stream has ended without any fragments, so serve an empty playlist
+        (Err(_), true) => String::from("#EXTM3U\n"),
+        // The index file has not been (re-)created yet or is missing
+        (Err(_), false) => return missing_playlist(source, state),
     };
 
+    // Rewrite the playlist
+    record_listed(&Playlist::parse(&playlist), source, config, state);
+    let playlist = rewrite_playlist(&playlist, source, config, state);
+
+    // Select the content coding, as the gzip and identity representations have different entity tags
+    let accepts_gzip =
+        request.field("Accept-Encoding").is_some_and(|value| headers::accepts_encoding(value.as_ref(), "gzip"));
+    let etag = headers::content_etag(playlist.as_bytes(), accepts_gzip.then_some("gzip"));
+
+    // Evaluate a conditional request if any, so that polling players only receive changed playlists
+    if request.field("If-None-Match").is_some_and(|value| headers::matches_etag(value.as_ref(), &etag)) {
+        let mut response = Response::new_status_reason(304, "Not Modified");
+        response.set_field("ETag", etag);
+        response.set_field("Vary", "Accept-Encoding");
+        return response;
+    }
+
+    // Compress the playlist if supported by the client
+    let (body, content_encoding, etag) = match accepts_gzip.then(|| gzip(playlist.as_bytes())) {
+        Some(Ok(compressed)) => (compressed, Some("gzip"), etag),
+        _ => {
+            // Serve the identity representation with its own entity tag
+            let etag = headers::content_etag(playlist.as_bytes(), None);
+            (playlist.into_bytes(), None, etag)
+        }
+    };
+
+    // Assemble response
+    let mut response = Response::new_200_ok();
+    response.set_body_data(body);
+
+    // Set headers and finalize request
+    response.set_content_type(config.RTSP2HLS_PLAYLISTMIME.to_string());
+    response.set_field("ETag", etag);
+    response.set_field("Vary", "Accept-Encoding");
+    if let Some(content_encoding) = content_encoding {
+        response.set_field("Content-Encoding", content_encoding);
+    }
+    response
+}
+
+/// Handles a GET request for `/info`
+pub fn get_info(source: &Source, config: &Config, state: &State) -> Response {
+    // Load the current playlist
+    let Ok(playlist) = read_playlist(source, config, state).map(|playlist| Playlist::parse(&playlist)) else {
+        // The index file has not been (re-)created yet or is missing
+        return missing_playlist(source, state);
+    };
+
+    // Serialize the playlist info
+    let optional = |value: Option<u64>| value.map_or_else(|| "null".to_string(), |value| value.to_string());
+    let info = format!(
+        r#"{{"targetDuration":{},"mediaSequence":{},"segments":{}}}"#,
+        optional(playlist.target_duration),
+        optional(playlist.media_sequence),
+        playlist.segments.len()
+    );
+
     // Assemble response
     let mut response = Response::new_200_ok();
-    let Ok(_) = response.set_body_file(file) else {
-        // We cannot process the index file
+    response.set_body_data(info);
+    response.set_content_type("application/json");
+    response
+}
+
+/// Handles a GET request for `/snapshot.jpg`
+pub fn get_snapshot(source: &Source, config: &Config, state: &State) -> Response {
+    // Get a current snapshot
+    let jpeg = match snapshot::get(source, config, state) {
+        Ok(Some(jpeg)) => jpeg,
+        Ok(None) => return warming_up(),
+        Err(e) => {
+            e.log_to_stderr();
+            return Response::new_500_internalservererror();
+        }
+    };
+
+    // Assemble response
+    let mut response = Response::new_200_ok();
+    response.set_body_data(jpeg.to_vec());
+    response.set_content_type("image/jpeg");
+    response.set_field("Cache-Control", "no-cache");
+    response
+}
+
+/// Handles a GET request for `/init.mp4`, i.e. the current fMP4 initialization segment
+pub fn get_init(source: &Source, config: &Config, state: &State) -> Response {
+    // Get the current initialization segment
+    let Ok(playlist) = read_playlist(source, config, state).map(|playlist| Playlist::parse(&playlist)) else {
+        return missing_playlist(source, state);
+    };
+    let Some(map) = playlist.map.filter(|map| !map.contains(['/', '\\'])) else {
+        // The stream has no (valid) initialization segment, e.g. because it is an MPEG-TS stream
+        return Response::new_404_notfound();
+    };
+
+    // Serve the initialization segment
+    let Ok(file) = File::open(source.directory.join(map)) else {
+        return Response::new_404_notfound();
+    };
+    let Ok(mut response) = full_response(file, config, state) else {
         return Response::new_500_internalservererror();
     };
 
     // Set headers and finalize request
-    response.set_content_type("application/vnd.apple.mpegurl");
+    // Note: The playlist versions the route with the entity tag of the segment, so it can be cached as immutable
+    response.set_content_type("video/mp4");
+    response.set_field("Cache-Control", "public, max-age=31536000, immutable");
     response
 }
 
 /// Serves a GET request for a HLS entry
-pub fn get_fragment(request: &Request, config: &Config) -> Response {
+pub fn get_fragment(
+    request: &Request,
+    peer: Option<SocketAddr>,
+    source: &Source,
+    config: &Config,
+    state: &State,
+) -> Response {
     // Extract fragment counter
-    // Note: Fragments follow the format `/live-%08d.ts`, this allows for some optimization
-    let Ok(target) = <[u8; 17]>::try_from(request.target.as_ref()) else {
-        // The request target is not a valid, absolute fragment name
+    // Note: Fragments follow the format `/<fragment prefix>%0<index width>d<extension>`
+    let extension = config.RTSP2HLS_CONTAINER.extension();
+    let Some(filename) = request.target.strip_prefix(b"/") else {
+        // The request target is not an absolute fragment name
         return Response::new_404_notfound();
     };
-
-    // Split path into segments
-    let prefix = &target[0..6];
-    let number = &target[6..14];
-    let suffix = &target[14..17];
-    let filename = &target[1..17];
-
-    // Validate fragment name format
-    let b"/live-" = prefix else {
+    let (prefix, width) = (Container::FRAGMENT_PREFIX, config.RTSP2HLS_INDEXWIDTH);
+    let max_length = prefix.len().saturating_add(width).saturating_add(extension.len());
+    let true = sanitize::is_plain_file_name(filename, max_length) else {
+        // The request target is not a plain file name (e.g. URL-encoded or a path traversal attempt)
+        return Response::new_404_notfound();
+    };
+    if config.RTSP2HLS_BYTERANGE && filename == byterange::MEDIA_FILE.as_bytes() {
+        // Serve the single media file in byte-range mode; it spans many fragments and is bounded by the watchdog
+        return get_file(request, peer, &source.directory.join(byterange::MEDIA_FILE), None, source, config, state);
+    }
+    let Some(number) = filename.strip_prefix(prefix.as_bytes()) else {
         // The request target prefix is invalid
         return Response::new_404_notfound();
     };
-    let true = number.iter().all(u8::is_ascii_digit) else {
+    let Some(number) = number.strip_suffix(extension.as_bytes()) else {
+        // The request target suffix is invalid
+        return Response::new_404_notfound();
+    };
+    let true = (number.len() == width && number.iter().all(u8::is_ascii_digit)) else {
         // The request target fragment counter is invalid
         return Response::new_404_notfound();
     };
-    let b".ts" = suffix else {
-        // The request target suffix is invalid
+    let (Ok(filename), Ok(number)) = (str::from_utf8(filename), str::from_utf8(number)) else {
+        // The request target is not valid UTF-8; this cannot happen for a validated plain file name
         return Response::new_404_notfound();
     };
 
     // Assemble path
-    // Note: This can never fail as we have validated that the file name is valid
-    let filename = str::from_utf8(&filename).expect("failed to parse ASCII filename");
-    let path = config.RTSP2HLS_TEMPDIR.join(filename);
+    let path = source.directory.join(filename);
+
+    // Serve the fragment from the ring buffer in memory mode
+    if config.RTSP2HLS_MEMORY {
+        return get_memory_fragment(request, peer, filename, source, config, state);
+    }
 
+    // Ensure the fragment has been finalized
+    let true = is_finalized(number, source, config, state) else {
+        // The fragment may still be written by hlssink, so don't serve a truncated file
+        return Response::new_404_notfound();
+    };
+    let true = (was_listed(number, source, state) || path.is_file()) else {
+        // The fragment has never been listed by the current pipeline, so it is not gone but has never existed
+        return Response::new_404_notfound();
+    };
+
+    // Serve the fragment
+    get_file(request, peer, &path, config.RTSP2HLS_MAXFRAGMENT, source, config, state)
+}
+
+/// Serves a GET request for a finalized file within the stream directory, and rejects files above the given size if any
+fn get_file(
+    request: &Request,
+    peer: Option<SocketAddr>,
+    path: &Path,
+    max_size: Option<u64>,
+    source: &Source,
+    config: &Config,
+    state: &State,
+) -> Response {
     // Open the file
-    let Ok(file) = File::open(path) else {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        // The fragment has been finalized and is therefore gone, e.g. because it has dropped out of the live window
+        Err(e) if e.kind() == ErrorKind::NotFound => return gone(),
         // We cannot open the fragment file
-        return Response::new_404_notfound();
+        Err(_) => return Response::new_404_notfound(),
+    };
+    let Ok(metadata) = file.metadata() else {
+        // We cannot access the fragment metadata
+        return Response::new_500_internalservererror();
+    };
+    if let Some(max_size) = max_size {
+        // A well-behaved pipeline never produces oversized fragments, so this indicates a runaway file
+        let (size, filename) = (metadata.len(), path.display());
+        if size > max_size {
+            error!("Refusing to serve oversized fragment {filename} ({size} bytes)").log_to_stderr();
+            return Response::new_500_internalservererror();
+        }
+    }
+
+    // Account the viewer and enforce the viewer limit if any
+    let true = state.viewers.admit(&source.name, peer.map(|peer| peer.ip()), config.RTSP2HLS_MAX_VIEWERS) else {
+        // The stream has reached its viewer limit
+        return viewer_limit_reached();
+    };
+
+    // Compute the validators
+    let etag = headers::etag(&metadata);
+    let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+    let last_modified = headers::format_http_date(modified);
+
+    // Reject malformed dates, as they would otherwise silently disable the condition
+    let if_modified_since = request.field("If-Modified-Since");
+    if if_modified_since.is_some_and(|value| headers::parse_http_date(value.as_ref()).is_none()) {
+        return Response::new_status_reason(400, "Bad Request");
+    }
+
+    // Evaluate a conditional request if any (see RFC 7232, section 6)
+    let not_modified = match (request.field("If-None-Match"), if_modified_since) {
+        (Some(if_none_match), _) => headers::matches_etag(if_none_match.as_ref(), &etag),
+        // Modification times are unreliable after a wall-clock jump, so ignore date-based conditions
+        (None, Some(_)) if state.is_clock_skewed() => false,
+        (None, Some(if_modified_since)) => headers::is_unmodified_since(if_modified_since.as_ref(), modified),
+        (None, None) => false,
+    };
+    if not_modified {
+        // The client already has the current fragment
+        let mut response = Response::new_status_reason(304, "Not Modified");
+        response.set_field("ETag", etag);
+        response.set_field("Last-Modified", last_modified);
+        return response;
+    }
+
+    // Evaluate a range request if any
+    let is_current = |validator: &[u8]| match state.is_clock_skewed() {
+        // Modification times are unreliable after a wall-clock jump, so only trust the entity tag
+        true => validator == etag.as_bytes(),
+        false => validator == etag.as_bytes() || validator == last_modified.as_bytes(),
+    };
+    let range = match request.field("Range") {
+        Some(range) => headers::parse_range(range.as_ref(), metadata.len()),
+        None => Some(ByteRange::Full),
+    };
+    let Some(range) = range else {
+        // The range is malformed; this is rejected even if the range would be ignored due to `If-Range`
+        return Response::new_status_reason(400, "Bad Request");
+    };
+    let range = match request.field("If-Range") {
+        // Only serve the range if the validator still matches (see RFC 7233, section 3.2)
+        Some(if_range) if !is_current(if_range.as_ref()) => ByteRange::Full,
+        _ => range,
     };
 
     // Assemble the response
-    let mut response = Response::new_200_ok();
-    let Ok(_) = response.set_body_file(file) else {
-        // We cannot process the index file
+    let body_length = match &range {
+        ByteRange::Full => metadata.len(),
+        ByteRange::Partial(range) => range.end.saturating_sub(range.start),
+        ByteRange::Unsatisfiable => 0,
+    };
+    let response = match range {
+        ByteRange::Full => full_response(file, config, state),
+        ByteRange::Partial(range) => partial_response(file, range, metadata.len(), state),
+        ByteRange::Unsatisfiable => Ok(unsatisfiable_response(metadata.len())),
+    };
+    let Ok(mut response) = response else {
+        // We cannot process the fragment file
         return Response::new_500_internalservererror();
     };
 
+    // Account the served fragment
+    if request.method.as_ref() == b"GET" && body_length > 0 {
+        state.stats.record_fragment(body_length);
+    }
+
+    // Set headers and finalize request
+    response.set_content_type(config.RTSP2HLS_FRAGMENTMIME.to_string());
+    response.set_field("Accept-Ranges", "bytes");
+    response.set_field("ETag", etag);
+    response.set_field("Last-Modified", last_modified);
+    response
+}
+
+/// Serves a GET request for a HLS entry from the in-memory ring buffer
+///
+/// # Note
+/// As fragment names are reused across restarts, conditional requests are not supported for in-memory fragments.
+fn get_memory_fragment(
+    request: &Request,
+    peer: Option<SocketAddr>,
+    name: &str,
+    source: &Source,
+    config: &Config,
+    state: &State,
+) -> Response {
+    // Get the fragment
+    // Note: Fragments are only pushed into the ring buffer once they are complete, so they are always finalized
+    let Some(fragment) = state.rings.fragment(&source.name, name) else {
+        return match state.rings.is_dropped(&source.name, name) {
+            true => gone(),
+            false => Response::new_404_notfound(),
+        };
+    };
+    let length = fragment.len() as u64;
+    if let Some(max_fragment) = config.RTSP2HLS_MAXFRAGMENT {
+        // A well-behaved pipeline never produces oversized fragments, so this indicates a runaway segment
+        if length > max_fragment {
+            error!("Refusing to serve oversized fragment {name} ({length} bytes)").log_to_stderr();
+            return Response::new_500_internalservererror();
+        }
+    }
+
+    // Account the viewer and enforce the viewer limit if any
+    let true = state.viewers.admit(&source.name, peer.map(|peer| peer.ip()), config.RTSP2HLS_MAX_VIEWERS) else {
+        // The stream has reached its viewer limit
+        return viewer_limit_reached();
+    };
+
+    // Evaluate a range request if any
+    let range = match request.field("Range") {
+        Some(range) => headers::parse_range(range.as_ref(), length),
+        None => Some(ByteRange::Full),
+    };
+    let Some(range) = range else {
+        // The range is malformed
+        return Response::new_status_reason(400, "Bad Request");
+    };
+    let (mut response, range) = match range {
+        ByteRange::Full => (Response::new_200_ok(), 0..length),
+        ByteRange::Partial(range) => {
+            let content_range = format!("bytes {}-{}/{length}", range.start, range.end.saturating_sub(1));
+            let mut response = Response::new_status_reason(206, "Partial Content");
+            response.set_field("Content-Range", content_range);
+            (response, range)
+        }
+        ByteRange::Unsatisfiable => return unsatisfiable_response(length),
+    };
+
+    // Assemble the response
+    let body_length = range.end.saturating_sub(range.start);
+    let mut body = Cursor::new(fragment);
+    body.set_position(range.start);
+    let body = body.take(body_length);
+    match &state.egress {
+        Some(pacer) => set_body_paced(&mut response, body, body_length, pacer.clone()),
+        None => {
+            response.set_field("Content-Length", body_length.to_string());
+            response.body = bytes::Source::new(body);
+        }
+    }
+
+    // Account the served fragment
+    if request.method.as_ref() == b"GET" && body_length > 0 {
+        state.stats.record_fragment(body_length);
+    }
+
     // Set headers and finalize request
-    response.set_content_type("video/mp2t");
+    response.set_content_type(config.RTSP2HLS_FRAGMENTMIME.to_string());
+    response.set_field("Accept-Ranges", "bytes");
     response
 }
+
+/// Whether the fragment with the given counter has been finalized, i.e. it is not newer than the newest fragment that
+/// is referenced by the current playlist
+///
+/// # Note
+/// Fragments that have already been dropped from the playlist are considered finalized too, so that lagging players can
+/// still fetch them as long as they exist.
+fn is_finalized(number: &str, source: &Source, config: &Config, state: &State) -> bool {
+    let Ok(number) = number.parse::<u64>() else {
+        // The fragment counter is out of range
+        return false;
+    };
+
+    // Get the newest referenced fragment
+    let playlist = Playlist::load(&source.playlist()).unwrap_or_default();
+    record_listed(&playlist, source, config, state);
+    let newest = listed_numbers(&playlist, config).max();
+    newest.is_some_and(|newest| number <= newest)
+}
+
+/// Whether the fragment with the given counter has been listed in a playlist of the current pipeline, i.e. it is not
+/// older than the oldest fragment that has been listed since the stream directory has been reset
+///
+/// # Note
+/// Only listed fragments may be reported as gone; other counters, e.g. made-up ones or from a previous pipeline, have
+/// never existed.
+fn was_listed(number: &str, source: &Source, state: &State) -> bool {
+    let Ok(number) = number.parse::<u64>() else {
+        // The fragment counter is out of range
+        return false;
+    };
+
+    let oldest_listed = state.listed.0.lock().unwrap_or_else(PoisonError::into_inner);
+    oldest_listed.get(&source.directory).is_some_and(|oldest| number >= *oldest)
+}
+
+/// Records the fragments that are listed by the given playlist of the given stream
+fn record_listed(playlist: &Playlist, source: &Source, config: &Config, state: &State) {
+    let Some(oldest) = listed_numbers(playlist, config).min() else {
+        // The playlist does not list any fragments yet
+        return;
+    };
+
+    let mut oldest_listed = state.listed.0.lock().unwrap_or_else(PoisonError::into_inner);
+    let entry = oldest_listed.entry(source.directory.clone()).or_insert(oldest);
+    *entry = oldest.min(*entry);
+}
+
+/// Gets the counters of all fragments that are listed by the given playlist
+fn listed_numbers<'a>(playlist: &'a Playlist, config: &Config) -> impl Iterator<Item = u64> + 'a {
+    let extension = config.RTSP2HLS_CONTAINER.extension();
+    (playlist.segments.iter())
+        .map(|segment| segment.uri.trim_start_matches('/'))
+        .filter_map(move |name| name.strip_prefix(Container::FRAGMENT_PREFIX)?.strip_suffix(extension))
+        .filter_map(|number| number.parse::<u64>().ok())
+}
+
+/// Parses the `_HLS_msn` and `_HLS_part` delivery directives from a query and returns the requested media sequence
+/// number
+///
+/// # Note
+/// As partial segments are not supported, `_HLS_part` is validated but otherwise ignored.
+#[allow(clippy::result_large_err, reason = "The error is the `400 Bad Request` response that is sent as-is")]
+fn parse_delivery_directives(query: &[u8]) -> Result<Option<u64>, Response> {
+    let (mut media_sequence, mut part) = (None, None);
+    for parameter in query.split(|byte| *byte == b'&') {
+        let mut parameter = parameter.splitn(2, |byte| *byte == b'=');
+        let (name, value) = (parameter.next().unwrap_or_default(), parameter.next().unwrap_or_default());
+        let value = str::from_utf8(value).ok().and_then(|value| value.parse::<u64>().ok());
+        match (name, value) {
+            (b"_HLS_msn", Some(value)) => media_sequence = Some(value),
+            (b"_HLS_part", Some(value)) => part = Some(value),
+            (b"_HLS_msn" | b"_HLS_part", None) => return Err(Response::new_status_reason(400, "Bad Request")),
+            _ => continue,
+        }
+    }
+
+    // A part without a media sequence number is invalid
+    match (media_sequence, part) {
+        (None, Some(_)) => Err(Response::new_status_reason(400, "Bad Request")),
+        (media_sequence, _) => Ok(media_sequence),
+    }
+}
+
+/// Blocks until the playlist contains the given media sequence number or the blocking reload times out
+#[allow(clippy::result_large_err, reason = "The error is the response that is sent as-is")]
+fn await_media_sequence(media_sequence: u64, source: &Source, config: &Config, state: &State) -> Result<(), Response> {
+    let deadline = Instant::now().checked_add(BLOCKING_RELOAD_TIMEOUT);
+    loop {
+        // Get the last media sequence number of the current playlist
+        let last = read_playlist(source, config, state).ok().and_then(|playlist| {
+            let playlist = Playlist::parse(&playlist);
+            let first = playlist.media_sequence.unwrap_or_default();
+            first.saturating_add(playlist.segments.len() as u64).checked_sub(1)
+        });
+        match last {
+            // The requested segment is available
+            Some(last) if last >= media_sequence => return Ok(()),
+            // The requested segment is too far in the future (see RFC 8216bis, section 6.2.5.2)
+            Some(last) if media_sequence > last.saturating_add(2) => {
+                return Err(Response::new_status_reason(400, "Bad Request"));
+            }
+            _ => (),
+        }
+
+        // Do not block if the stream has ended or the blocking reload has timed out
+        if state.supervision.is_unreachable(&source.name) {
+            return Ok(());
+        }
+        if deadline.is_none_or(|deadline| Instant::now() >= deadline) {
+            return Err(warming_up());
+        }
+        thread::sleep(BLOCKING_RELOAD_POLL_INTERVAL);
+    }
+}
+
+/// Rewrites the playlist of the given stream according to the config and the stream state
+fn rewrite_playlist(playlist: &str, source: &Source, config: &Config, state: &State) -> String {
+    let mut program_dates = program_dates(playlist, source, config, state).into_iter();
+    let byte_ranges = byte_ranges(source, config, state);
+    let mut rewritten = String::with_capacity(playlist.len());
+    for line in playlist.lines() {
+        // Drop the playlist type and the server control if any as we set them ourselves
+        if line.starts_with("#EXT-X-PLAYLIST-TYPE:") || line.starts_with("#EXT-X-SERVER-CONTROL:") {
+            continue;
+        }
+        if config.RTSP2HLS_PROGRAMDATETIME && line.starts_with("#EXT-X-PROGRAM-DATE-TIME:") {
+            continue;
+        }
+
+        // Tag the wall-clock start time of the next segment if enabled
+        if line.starts_with("#EXTINF:") {
+            if let Some(Some(program_date)) = program_dates.next() {
+                rewritten.push_str(&format!("#EXT-X-PROGRAM-DATE-TIME:{program_date}\n"));
+            }
+        }
+
+        // Point appended segments to their byte range within the media file
+        if let Some(range) = byte_ranges.get(line.trim_start_matches('/')) {
+            let (length, media_file) = (range.end.saturating_sub(range.start), byterange::MEDIA_FILE);
+            rewritten.push_str(&format!("#EXT-X-BYTERANGE:{length}@{}\n", range.start));
+            rewritten.push_str(&format!("{}{}/{media_file}\n", config.RTSP2HLS_PATHPREFIX, source.path));
+            continue;
+        }
+
+        match (line.strip_prefix("#EXTINF:"), config.RTSP2HLS_EXTINF_PRECISION) {
+            // Normalize the segment duration precision if configured
+            (Some(extinf), Some(precision)) => rewritten.push_str(&playlist::format_extinf(extinf, precision)),
+            // Point the initialization segment to its stable route
+            (None, _) if line.starts_with("#EXT-X-MAP:") => rewritten.push_str(&rewrite_map(line, source, config)),
+            // Byte ranges require at least protocol version 4
+            (None, _) if line.starts_with("#EXT-X-VERSION:") && !byte_ranges.is_empty() => {
+                let version = line.trim_start_matches("#EXT-X-VERSION:").parse().unwrap_or(0u32);
+                rewritten.push_str(&format!("#EXT-X-VERSION:{}", version.max(4)));
+            }
+            // Prepend the path prefix to relative segment URIs
+            (None, _) if !line.is_empty() && !line.starts_with('#') && !line.contains("://") => {
+                let uri = line.trim_start_matches('/');
+                rewritten.push_str(&format!("{}{}/{uri}", config.RTSP2HLS_PATHPREFIX, source.path));
+            }
+            _ => rewritten.push_str(line),
+        }
+        rewritten.push('\n');
+
+        // Tag the playlist type and the server capabilities right after the header
+        if line == "#EXTM3U" && config.RTSP2HLS_PLAYLISTTYPE == PlaylistType::Event {
+            rewritten.push_str("#EXT-X-PLAYLIST-TYPE:EVENT\n");
+        }
+        if line == "#EXTM3U" && config.RTSP2HLS_LOWLATENCY {
+            rewritten.push_str("#EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES\n");
+        }
+    }
+
+    // Mark the stream as ended if the source is gone for good
+    if state.supervision.is_unreachable(&source.name) && !playlist.contains("#EXT-X-ENDLIST") {
+        rewritten.push_str("#EXT-X-ENDLIST\n");
+    }
+    rewritten
+}
+
+/// Computes the wall-clock start time of each segment of the given playlist in segment order if enabled, i.e. the time
+/// the fragment has been finalized at minus its duration
+///
+/// # Note
+/// Modification times are unreliable after a wall-clock jump, so no start times are computed while the clock is skewed.
+fn program_dates(playlist: &str, source: &Source, config: &Config, state: &State) -> Vec<Option<String>> {
+    let true = (config.RTSP2HLS_PROGRAMDATETIME && !state.is_clock_skewed()) else {
+        return Vec::new();
+    };
+
+    // Compute the start time of each segment
+    let playlist = Playlist::parse(playlist);
+    let program_date = |segment: &playlist::Segment| {
+        let name = segment.uri.trim_start_matches('/');
+        let finished = match config.RTSP2HLS_MEMORY {
+            true => state.rings.finished(&source.name, name)?,
+            false if name.contains(['/', '\\']) => return None,
+            false => fs::metadata(source.directory.join(name)).ok()?.modified().ok()?,
+        };
+        let duration = Duration::try_from_secs_f64(segment.duration).ok()?;
+        Some(headers::format_iso8601(finished.checked_sub(duration)?))
+    };
+    playlist.segments.iter().map(program_date).collect()
+}
+
+/// Returns the byte range of each segment within the media file if the byte-range mode is enabled
+///
+/// # Note
+/// The segments are appended to the media file by the watchdog, so segments that have not been appended yet (or that
+/// cannot be appended) are served as discrete fragments instead.
+fn byte_ranges(source: &Source, config: &Config, state: &State) -> BTreeMap<String, Range<u64>> {
+    match config.RTSP2HLS_BYTERANGE {
+        true => state.indexes.ranges(source),
+        false => BTreeMap::new(),
+    }
+}
+
+/// Rewrites the given `#EXT-X-MAP`-tag to point to the stable `/init.mp4` route of the given stream
+///
+/// # Note
+/// The route is versioned with the entity tag of the current initialization segment, so that it can be cached as
+/// immutable even if the muxer creates a new initialization segment with different contents.
+fn rewrite_map(line: &str, source: &Source, config: &Config) -> String {
+    let version = (playlist::quoted_attribute(line.trim_start_matches("#EXT-X-MAP:"), "URI"))
+        .filter(|uri| !uri.contains(['/', '\\']))
+        .and_then(|uri| fs::metadata(source.directory.join(uri)).ok())
+        .map(|metadata| headers::etag(&metadata).trim_matches('"').to_string())
+        .unwrap_or_default();
+    format!(r#"#EXT-X-MAP:URI="{}{}/init.mp4?v={version}""#, config.RTSP2HLS_PATHPREFIX, source.path)
+}
+
+/// Reads the current playlist of the given stream, either from the temp directory or from the ring buffer in memory
+/// mode
+pub fn read_playlist(source: &Source, config: &Config, state: &State) -> Result<String, io::Error> {
+    // Note: The pending reset is taken before the read, so that a concurrent reset cannot be cleared by a read of the
+    // removed playlist
+    let reset = state.resets.pending(&source.directory);
+    let playlist = match config.RTSP2HLS_MEMORY {
+        true => state.rings.playlist(&source.name).ok_or_else(|| io::Error::from(ErrorKind::NotFound))?,
+        false => fs::read_to_string(source.playlist())?,
+    };
+
+    // The stream has (re-)created its playlist, so it is not warming up anymore
+    // Note: After a purge, the running worker keeps listing the removed fragments until they have dropped out of its
+    // playlist, so the stream is warming up until then
+    if let Some(generation) = reset {
+        if !config.RTSP2HLS_MEMORY && lists_removed(&playlist, source) {
+            return Err(io::Error::from(ErrorKind::NotFound));
+        }
+        state.resets.clear(&source.directory, generation);
+    }
+    Ok(playlist)
+}
+
+/// Whether the given playlist of the given stream references a fragment that does not exist within the stream directory
+fn lists_removed(playlist: &str, source: &Source) -> bool {
+    let playlist = Playlist::parse(playlist);
+    (playlist.segments.iter()).any(|segment| !source.directory.join(segment.uri.trim_start_matches('/')).is_file())
+}
+
+/// Compresses the given data with gzip
+fn gzip(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+/// Creates a `503 Service Unavailable` response to signal that the stream is warming up
+fn warming_up() -> Response {
+    let mut response = Response::new_status_reason(503, "Service Unavailable");
+    response.set_field("Retry-After", "1");
+    response
+}
+
+/// Creates the response for a missing playlist of the given stream
+///
+/// # Note
+/// After the HLS artifacts have been removed, e.g. by a worker restart or a purge, the stream is warming up until the
+/// new playlist has been written; otherwise, the playlist is genuinely missing.
+fn missing_playlist(source: &Source, state: &State) -> Response {
+    match is_warming_up(source, state) {
+        true => warming_up(),
+        false => Response::new_404_notfound(),
+    }
+}
+
+/// Whether the given stream is warming up, i.e. its HLS artifacts have been removed and its new playlist has not been
+/// read yet
+pub fn is_warming_up(source: &Source, state: &State) -> bool {
+    state.resets.pending(&source.directory).is_some()
+}
+
+/// Creates a `410 Gone` response to signal that a fragment existed but has been removed, so that players skip it
+/// instead of retrying
+fn gone() -> Response {
+    Response::new_status_reason(410, "Gone")
+}
+
+/// Creates a `503 Service Unavailable` response to signal that the stream has reached its viewer limit
+fn viewer_limit_reached() -> Response {
+    let mut response = Response::new_status_reason(503, "Service Unavailable");
+    response.set_field("Retry-After", RtspClient::SEGMENT_LENGTH.as_secs().to_string());
+    response
+}
+
+/// Creates a `200 OK` response with the given file as body, read with the configured buffer size
+///
+/// # Note
+/// `ehttpd` copies response bodies through userspace buffers and does not provide a zero-copy (i.e. `sendfile`) body
+/// API, so we cannot hand the file descriptor to the kernel. Instead, we read fragments with a large buffer, which
+/// keeps the amount of read syscalls per fragment low as fragments are usually served from the page cache. If enabled,
+/// the next chunk is read ahead via `io_uring` while the current chunk is written to the client.
+fn full_response(file: File, config: &Config, state: &State) -> Result<Response, Error> {
+    let length = file.metadata()?.len();
+    let file = FragmentReader::new(file, config);
+
+    // Assemble the response
+    let mut response = Response::new_200_ok();
+    match &state.egress {
+        Some(pacer) => set_body_paced(&mut response, file, length, pacer.clone()),
+        None => {
+            response.set_field("Content-Length", length.to_string());
+            response.body = bytes::Source::new(file);
+        }
+    }
+    Ok(response)
+}
+
+/// Creates a `206 Partial Content` response with the given range of the file as body
+fn partial_response(mut file: File, range: Range<u64>, length: u64, state: &State) -> Result<Response, Error> {
+    // Read the requested range
+    let mut body = Vec::new();
+    file.seek(SeekFrom::Start(range.start))?;
+    file.take(range.end.saturating_sub(range.start)).read_to_end(&mut body)?;
+
+    // Assemble the response
+    let content_range = format!("bytes {}-{}/{length}", range.start, range.end.saturating_sub(1));
+    let mut response = Response::new_status_reason(206, "Partial Content");
+    response.set_field("Content-Range", content_range);
+    match &state.egress {
+        Some(pacer) => {
+            set_body_paced(&mut response, Cursor::new(body), range.end.saturating_sub(range.start), pacer.clone());
+        }
+        None => response.set_body_data(body),
+    }
+    Ok(response)
+}
+
+/// Sets the given reader as response body, paced by the given pacer
+fn set_body_paced<T>(response: &mut Response, body: T, length: u64, pacer: Arc<Pacer>)
+where
+    T: Read + Debug + Send + Sync + 'static,
+{
+    let body = PacedReader::new(body, pacer);
+    response.set_field("Content-Length", length.to_string());
+    response.body = bytes::Source::new(body);
+}
+
+/// Creates a `416 Range Not Satisfiable` response for a resource with the given length
+fn unsatisfiable_response(length: u64) -> Response {
+    let mut response = Response::new_status_reason(416, "Range Not Satisfiable");
+    response.set_field("Content-Range", format!("bytes */{length}"));
+    response
+}
+
+/// Removes all HLS artifacts (i.e. the playlist, all fragments, all initialization segments and the media file of the
+/// byte-range mode) from the directory of the given stream
+///
+/// # Note
+/// Unrelated files within the directory are left untouched.
+pub fn remove_artifacts(source: &Source, state: &State) -> Result<(), Error> {
+    // Forget the listed fragments as the fragment counter restarts with the next pipeline
+    let directory = source.directory.as_path();
+    let mut oldest_listed = state.listed.0.lock().unwrap_or_else(PoisonError::into_inner);
+    oldest_listed.remove(directory);
+    drop(oldest_listed);
+
+    // Mark the stream as warming up until the next pipeline has written its playlist
+    // Note: The stream is marked again after the removal, so that a read of the old playlist during the removal cannot
+    // clear the mark
+    let directory_entries = fs::read_dir(directory)?;
+    state.resets.mark(directory);
+    for directory_entry in directory_entries.flatten() {
+        // Only delete known HLS artifacts
+        let name = directory_entry.file_name();
+        let true = is_artifact(name.as_encoded_bytes(), source) else {
+            continue;
+        };
+
+        // Delete the artifact; it is fine if it has been removed concurrently
+        let Err(e) = fs::remove_file(directory_entry.path()) else {
+            continue;
+        };
+        if e.kind() != ErrorKind::NotFound {
+            return Err(e.into());
+        }
+    }
+    state.resets.mark(directory);
+    Ok(())
+}
+
+/// Removes the unique temp directory of this run including all stream directories if enabled
+///
+/// # Note
+/// This is called on shutdown; failures are only logged, as the process exits anyway.
+pub fn remove_unique_dir(config: &Config) {
+    let true = config.RTSP2HLS_UNIQUEDIR else {
+        return;
+    };
+    let tempdir = &config.RTSP2HLS_TEMPDIR;
+    if let Err(e) = fs::remove_dir_all(tempdir) {
+        if e.kind() != ErrorKind::NotFound {
+            error!(with: e, "Failed to remove the temp directory {}", tempdir.display()).log_to_stderr();
+        }
+    }
+}
+
+/// Whether the given file name is a known HLS artifact of the given stream
+///
+/// # Note
+/// Fragments of both container formats are considered artifacts, so that the fragments of a previous run with another
+/// container format are removed too.
+fn is_artifact(name: &[u8], source: &Source) -> bool {
+    // Matches names like `<prefix><digits><extension>`
+    let is_numbered = |prefix: &str, extension: &str| {
+        let digits = name.strip_prefix(prefix.as_bytes()).and_then(|name| name.strip_suffix(extension.as_bytes()));
+        digits.is_some_and(|digits| !digits.is_empty() && digits.iter().all(u8::is_ascii_digit))
+    };
+
+    // Check the known artifacts
+    let prefix = Container::FRAGMENT_PREFIX;
+    let is_fragment =
+        is_numbered(prefix, Container::MpegTs.extension()) || is_numbered(prefix, Container::Fmp4.extension());
+    let is_init_segment = is_numbered(INIT_SEGMENT_PREFIX, INIT_SEGMENT_EXTENSION);
+    let is_media_file = name == byterange::MEDIA_FILE.as_bytes();
+    name == source.playlist_name.as_bytes() || is_fragment || is_init_segment || is_media_file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        get_fragment, get_index, is_artifact, is_finalized, parse_delivery_directives, remove_artifacts,
+        rewrite_playlist, was_listed, BlockingReloads, Resets,
+    };
+    use crate::config::{Config, Container, PlaylistType, Source};
+    use crate::error;
+    use crate::error::Error;
+    use crate::headers;
+    use crate::playlist::{Playlist, Segment};
+    use crate::state::State;
+    use ehttpd::bytes;
+    use ehttpd::http::Request;
+    use std::borrow::Cow;
+    use std::io::{BufReader, Cursor, Read};
+    use std::time::UNIX_EPOCH;
+    use std::{env, fs};
+
+    /// A playlist as written by `hlssink`
+    const PLAYLIST: &str = "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-MEDIA-SEQUENCE:4\n#EXT-X-TARGETDURATION:1\n\
+        #EXTINF:1.000000123,\nlive-00000004.ts\n#EXTINF:0.98,\n/live-00000005.ts\n";
+
+    /// Creates a test source with the given name within its own directory that contains [`PLAYLIST`]
+    fn stream(name: &str) -> Result<Source, Error> {
+        let source = Source::for_tests(name);
+        fs::create_dir_all(&source.directory)?;
+        fs::write(source.playlist(), PLAYLIST)?;
+        Ok(source)
+    }
+
+    /// Creates a request stream for the given target with the given header fields
+    fn request_stream(target: &str, fields: &str) -> bytes::Source {
+        let head = format!("GET {target} HTTP/1.1\r\nHost: localhost\r\n{fields}\r\n");
+        bytes::Source::new(BufReader::new(Cursor::new(head.into_bytes())))
+    }
+
+    /// Parses a request from the given stream
+    fn request(stream: &mut bytes::Source) -> Result<Request<'_>, Error> {
+        Request::from_stream(stream)?.ok_or_else(|| error!("Missing test request"))
+    }
+
+    /// Serves the test fragment for a request with the given header fields, and returns the status and the body
+    fn serve(fields: &str, source: &Source, config: &Config, state: &State) -> Result<(Vec<u8>, Vec<u8>), Error> {
+        let mut stream = request_stream("/live-00000004.ts", fields);
+        let mut response = get_fragment(&request(&mut stream)?, None, source, config, state);
+        let mut body = Vec::new();
+        response.body.read_to_end(&mut body)?;
+        Ok((response.status.to_vec(), body))
+    }
+
+    /// A range is only served if the `If-Range` validator matches the current entity tag or modification date
+    #[test]
+    fn get_fragment_if_range() -> Result<(), Error> {
+        let config = Config::for_tests()?;
+        let state = State::default();
+        let source = stream("if-range")?;
+        let path = source.directory.join("live-00000004.ts");
+        fs::write(&path, b"0123456789")?;
+
+        // Compute the current validators
+        let metadata = fs::metadata(&path)?;
+        let etag = headers::etag(&metadata);
+        let last_modified = headers::format_http_date(metadata.modified().unwrap_or(UNIX_EPOCH));
+
+        // Matching validators yield the range
+        for validator in [&etag, &last_modified] {
+            let fields = format!("Range: bytes=2-5\r\nIf-Range: {validator}\r\n");
+            let (status, body) = serve(&fields, &source, &config, &state)?;
+            assert_eq!((status.as_slice(), body.as_slice()), (b"206".as_slice(), b"2345".as_slice()), "{validator}");
+        }
+
+        // Mismatching validators yield the full body
+        for validator in ["\"0-0\"", "Thu, 01 Jan 1970 00:00:00 GMT"] {
+            let fields = format!("Range: bytes=2-5\r\nIf-Range: {validator}\r\n");
+            let (status, body) = serve(&fields, &source, &config, &state)?;
+            assert_eq!(
+                (status.as_slice(), body.as_slice()),
+                (b"200".as_slice(), b"0123456789".as_slice()),
+                "{validator}"
+            );
+        }
+
+        // Modification dates are not trusted after a wall-clock jump
+        state.set_clock_skewed(true);
+        let fields = format!("Range: bytes=2-5\r\nIf-Range: {last_modified}\r\n");
+        let (status, body) = serve(&fields, &source, &config, &state)?;
+        assert_eq!((status.as_slice(), body.as_slice()), (b"200".as_slice(), b"0123456789".as_slice()));
+
+        fs::remove_dir_all(&source.directory)?;
+        Ok(())
+    }
+
+    /// Segment URIs are prefixed with the stream path and the path prefix
+    #[test]
+    fn rewrite_playlist_uris() -> Result<(), Error> {
+        let mut config = Config::for_tests()?;
+        let state = State::default();
+        (config.RTSP2HLS_PATHPREFIX, config.RTSP2HLS_EXTINF_PRECISION) = (Cow::Borrowed("/prefix"), None);
+
+        let rewritten = rewrite_playlist(PLAYLIST, &Source::for_tests("cam0"), &config, &state);
+        let expected = "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-MEDIA-SEQUENCE:4\n#EXT-X-TARGETDURATION:1\n\
+            #EXTINF:1.000000123,\n/prefix/cam0/live-00000004.ts\n#EXTINF:0.98,\n/prefix/cam0/live-00000005.ts\n";
+        assert_eq!(rewritten, expected);
+        Ok(())
+    }
+
+    /// Event playlists are tagged right after the header, and the playlist type of `hlssink` is dropped
+    #[test]
+    fn rewrite_playlist_event() -> Result<(), Error> {
+        let mut config = Config::for_tests()?;
+        let state = State::default();
+        (config.RTSP2HLS_PLAYLISTTYPE, config.RTSP2HLS_EXTINF_PRECISION) = (PlaylistType::Event, None);
+
+        let playlist = "#EXTM3U\n#EXT-X-PLAYLIST-TYPE:VOD\n#EXTINF:1.0,\nlive-00000000.ts\n";
+        let rewritten = rewrite_playlist(playlist, &Source::for_tests("cam0"), &config, &state);
+        assert!(rewritten.starts_with("#EXTM3U\n#EXT-X-PLAYLIST-TYPE:EVENT\n#EXTINF:1.0,\n"), "{rewritten}");
+        assert!(!rewritten.contains("VOD"), "{rewritten}");
+        Ok(())
+    }
+
+    /// Segment durations are normalized to the configured precision, so that the total duration only deviates by the
+    /// rounding of each segment
+    #[test]
+    fn rewrite_playlist_extinf_precision() -> Result<(), Error> {
+        let mut config = Config::for_tests()?;
+        let state = State::default();
+        config.RTSP2HLS_EXTINF_PRECISION = Some(3);
+
+        let rewritten = rewrite_playlist(PLAYLIST, &Source::for_tests("cam0"), &config, &state);
+        assert!(rewritten.contains("#EXTINF:1.000,\n"));
+        assert!(rewritten.contains("#EXTINF:0.980,\n"));
+
+        // Rewrite a playlist with several segments
+        let playlist = "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-MEDIA-SEQUENCE:4\n#EXT-X-TARGETDURATION:2\n\
+            #EXTINF:1.000000123,\nlive-00000004.ts\n#EXTINF:0.98,\nlive-00000005.ts\n\
+            #EXTINF:1.0416666,\nlive-00000006.ts\n#EXTINF:1.2345678,\nlive-00000007.ts\n\
+            #EXTINF:0.9995,\nlive-00000008.ts\n";
+        let rewritten = rewrite_playlist(playlist, &Source::for_tests("cam0"), &config, &state);
+        for line in rewritten.lines().filter_map(|line| line.strip_prefix("#EXTINF:")) {
+            let (_, decimals) = line.trim_end_matches(',').split_once('.').ok_or_else(|| error!("Missing decimals"))?;
+            assert_eq!(decimals.len(), 3);
+        }
+        let (original, rewritten) = (Playlist::parse(playlist).segments, Playlist::parse(&rewritten).segments);
+        assert_eq!(rewritten.len(), 5);
+
+        // Compare the total durations, where each segment may be rounded by up to half a unit of the third decimal
+        let total = |segments: &[Segment]| segments.iter().map(|segment| segment.duration).sum::<f64>();
+        let tolerance = 0.0005 * rewritten.len() as f64;
+        assert!((total(&rewritten) - total(&original)).abs() <= tolerance);
+        Ok(())
+    }
+
+    /// Blocking playlist reloads are only advertised in low-latency mode
+    #[test]
+    fn rewrite_playlist_server_control() -> Result<(), Error> {
+        let mut config = Config::for_tests()?;
+        let state = State::default();
+        config.RTSP2HLS_LOWLATENCY = false;
+        let rewritten = rewrite_playlist(PLAYLIST, &Source::for_tests("cam0"), &config, &state);
+        assert!(!rewritten.contains("#EXT-X-SERVER-CONTROL"));
+
+        config.RTSP2HLS_LOWLATENCY = true;
+        let rewritten = rewrite_playlist(PLAYLIST, &Source::for_tests("cam0"), &config, &state);
+        assert!(rewritten.starts_with("#EXTM3U\n#EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES\n"));
+        Ok(())
+    }
+
+    /// The media sequence number is parsed from the delivery directives, and other parameters are ignored
+    #[test]
+    fn parse_delivery_directives_valid() {
+        assert_eq!(parse_delivery_directives(b"").ok(), Some(None));
+        assert_eq!(parse_delivery_directives(b"v=1&other").ok(), Some(None));
+        assert_eq!(parse_delivery_directives(b"_HLS_msn=42").ok(), Some(Some(42)));
+        assert_eq!(parse_delivery_directives(b"v=1&_HLS_msn=42&_HLS_part=3").ok(), Some(Some(42)));
+    }
+
+    /// Malformed delivery directives and parts without a media sequence number are rejected
+    #[test]
+    fn parse_delivery_directives_invalid() {
+        assert!(parse_delivery_directives(b"_HLS_msn").is_err());
+        assert!(parse_delivery_directives(b"_HLS_msn=").is_err());
+        assert!(parse_delivery_directives(b"_HLS_msn=-1").is_err());
+        assert!(parse_delivery_directives(b"_HLS_msn=abc").is_err());
+        assert!(parse_delivery_directives(b"_HLS_msn=18446744073709551616").is_err());
+        assert!(parse_delivery_directives(b"_HLS_msn=1&_HLS_part=x").is_err());
+        assert!(parse_delivery_directives(b"_HLS_part=1").is_err());
+    }
+
+    /// Fragments up to the newest listed fragment are finalized, including dropped ones
+    #[test]
+    fn is_finalized_listed() -> Result<(), Error> {
+        let mut config = Config::for_tests()?;
+        let state = State::default();
+        config.RTSP2HLS_CONTAINER = Container::MpegTs;
+        let source = stream("finalized")?;
+
+        assert!(is_finalized("00000000", &source, &config, &state));
+        assert!(is_finalized("00000004", &source, &config, &state));
+        assert!(is_finalized("00000005", &source, &config, &state));
+        assert!(!is_finalized("00000006", &source, &config, &state));
+        assert!(!is_finalized("99999999999999999999", &source, &config, &state));
+
+        fs::remove_dir_all(&source.directory)?;
+        Ok(())
+    }
+
+    /// No fragment is finalized without a playlist
+    #[test]
+    fn is_finalized_no_playlist() -> Result<(), Error> {
+        let config = Config::for_tests()?;
+        let state = State::default();
+        let source = Source::for_tests("finalized-no-playlist");
+        assert!(!is_finalized("00000000", &source, &config, &state));
+        Ok(())
+    }
+
+    /// Only fragments that have been listed since the stream directory has been reset may be reported as gone
+    #[test]
+    fn was_listed_since_reset() -> Result<(), Error> {
+        let mut config = Config::for_tests()?;
+        let state = State::default();
+        config.RTSP2HLS_CONTAINER = Container::MpegTs;
+        let source = stream("listed")?;
+        assert!(!was_listed("00000004", &source, &state));
+
+        // Observe the playlist
+        assert!(is_finalized("00000005", &source, &config, &state));
+        assert!(!was_listed("00000003", &source, &state));
+        assert!(was_listed("00000004", &source, &state));
+        assert!(was_listed("00000005", &source, &state));
+
+        // Reset the stream directory
+        remove_artifacts(&source, &state)?;
+        assert!(!was_listed("00000004", &source, &state));
+
+        fs::remove_dir_all(&source.directory)?;
+        Ok(())
+    }
+
+    /// A reset stream is warming up until the next pipeline has written its playlist, while a playlist that goes
+    /// missing afterwards is not found
+    #[test]
+    fn get_index_after_reset() -> Result<(), Error> {
+        let config = Config::for_tests()?;
+        let state = State::default();
+        let source = stream("index-reset")?;
+        let fragment = source.directory.join("live-00000004.ts");
+        fs::write(&fragment, b"fragment")?;
+        let mut stream = request_stream("/index.m3u8", "");
+        let request = request(&mut stream)?;
+
+        // Reset the stream directory
+        remove_artifacts(&source, &state)?;
+        assert!(!fragment.exists() && !source.playlist().exists());
+        assert_eq!(get_index(&request, b"", &source, &config, &state).status.as_ref(), b"503");
+
+        // Let the next pipeline write its fragments and its playlist
+        for name in ["live-00000004.ts", "live-00000005.ts"] {
+            fs::write(source.directory.join(name), b"fragment")?;
+        }
+        fs::write(source.playlist(), PLAYLIST)?;
+        assert_eq!(get_index(&request, b"", &source, &config, &state).status.as_ref(), b"200");
+
+        // Remove the playlist without a reset
+        fs::remove_file(source.playlist())?;
+        assert_eq!(get_index(&request, b"", &source, &config, &state).status.as_ref(), b"404");
+
+        fs::remove_dir_all(&source.directory)?;
+        Ok(())
+    }
+
+    /// The gzip and identity representations of the playlist have different entity tags, which are only matched by
+    /// conditional requests for the same representation
+    #[test]
+    fn get_index_etag_encoding() -> Result<(), Error> {
+        let config = Config::for_tests()?;
+        let state = State::default();
+        let source = stream("index-etag")?;
+        for name in ["live-00000004.ts", "live-00000005.ts"] {
+            fs::write(source.directory.join(name), b"fragment")?;
+        }
+
+        // Gets the status and the entity tag for a request with the given header fields
+        let get = |fields: &str| -> Result<(Vec<u8>, String), Error> {
+            let mut stream = request_stream("/index.m3u8", fields);
+            let response = get_index(&request(&mut stream)?, b"", &source, &config, &state);
+            let etag = (response.fields.iter())
+                .find(|(name, _)| name.as_ref().eq_ignore_ascii_case(b"ETag"))
+                .map(|(_, value)| String::from_utf8_lossy(value.as_ref()).into_owned())
+                .ok_or_else(|| error!("Missing entity tag"))?;
+            Ok((response.status.to_vec(), etag))
+        };
+
+        // Each representation has its own strong entity tag
+        let (_, identity) = get("")?;
+        let (_, gzip) = get("Accept-Encoding: gzip\r\n")?;
+        assert_ne!(identity, gzip);
+        assert!(identity.starts_with('"') && gzip.starts_with('"') && gzip.ends_with(r#"-gzip""#));
+
+        // An entity tag only matches its own representation
+        assert_eq!(get(&format!("If-None-Match: {gzip}\r\n"))?.0, b"200");
+        assert_eq!(get(&format!("Accept-Encoding: gzip\r\nIf-None-Match: {gzip}\r\n"))?.0, b"304");
+        assert_eq!(get(&format!("If-None-Match: {identity}\r\n"))?.0, b"304");
+        assert_eq!(get(&format!("Accept-Encoding: gzip\r\nIf-None-Match: {identity}\r\n"))?.0, b"200");
+
+        fs::remove_dir_all(&source.directory)?;
+        Ok(())
+    }
+
+    /// A read that has started before a reset cannot clear it
+    #[test]
+    fn resets_generation() -> Result<(), Error> {
+        let (resets, directory) = (Resets::default(), env::temp_dir());
+        resets.mark(&directory);
+        let generation = resets.pending(&directory).ok_or_else(|| error!("Missing pending reset"))?;
+
+        // A concurrent reset is not cleared
+        resets.mark(&directory);
+        resets.clear(&directory, generation);
+        let generation = resets.pending(&directory).ok_or_else(|| error!("Missing concurrent reset"))?;
+
+        // The current reset is cleared
+        resets.clear(&directory, generation);
+        assert_eq!(resets.pending(&directory), None);
+        Ok(())
+    }
+
+    /// Blocking reloads beyond the limit are refused until a reserved blocking reload is released
+    #[test]
+    fn blocking_reloads_limit() {
+        let reloads = BlockingReloads::default();
+        let first = reloads.try_reserve(2);
+        let second = reloads.try_reserve(2);
+        assert!(first.is_some() && second.is_some());
+        assert!(reloads.try_reserve(2).is_none());
+
+        drop(first);
+        assert!(reloads.try_reserve(2).is_some());
+        assert!(BlockingReloads::default().try_reserve(0).is_none());
+    }
+
+    /// Only the fragments, the configured playlist, the initialization segments and the media file are artifacts
+    #[test]
+    fn is_artifact_strict() {
+        let source = Source::for_tests("artifacts");
+        for name in ["live-00000004.ts", "live-7.m4s", "index.m3u8", "init00000.mp4", "stream.ts"] {
+            assert!(is_artifact(name.as_bytes(), &source), "{name}");
+        }
+        for name in ["live-.ts", "live-0a.ts", "recording.ts", "other.m3u8", "initial.mp4", "init.mp4", "notes.txt"] {
+            assert!(!is_artifact(name.as_bytes(), &source), "{name}");
+        }
+    }
+}
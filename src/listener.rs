@@ -0,0 +1,397 @@
+//! Listener helpers for the HLS HTTP server
+
+use crate::config::Listen;
+use crate::drain;
+use crate::error;
+use crate::error::Error;
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::convert::Infallible;
+use std::fs;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+/// A listener for incoming HTTP connections
+#[derive(Debug)]
+pub enum Listener {
+    /// A TCP listener
+    Tcp(TcpListener),
+    /// A Unix domain socket listener
+    #[cfg(unix)]
+    Unix(UnixSocketListener),
+}
+impl Listener {
+    /// The connection backlog for dual-stack sockets (which is the same value as used by `std`)
+    const BACKLOG: i32 = 128;
+    /// The maximum amount of attempts to bind a TCP listener if the address is in use
+    const BIND_ATTEMPTS: u32 = 5;
+    /// The initial delay between two bind attempts, which doubles after each attempt
+    const BIND_BACKOFF: Duration = Duration::from_millis(250);
+    /// The initial delay after a failed accept, which doubles after each consecutive failure
+    const ACCEPT_BACKOFF: Duration = Duration::from_millis(10);
+    /// The maximum delay after a failed accept
+    const ACCEPT_BACKOFF_MAX: Duration = Duration::from_secs(1);
+
+    /// Binds a new listener to the given listen address
+    pub fn bind(listen: &Listen) -> Result<Self, Error> {
+        match listen {
+            Listen::Tcp(address) => Ok(Self::Tcp(Self::bind_tcp_retry(address)?)),
+            #[cfg(unix)]
+            Listen::Unix(path) => Ok(Self::Unix(UnixSocketListener::bind(path)?)),
+        }
+    }
+
+    /// Binds a TCP listener to the given address, and retries with exponential backoff if the address is in use
+    ///
+    /// # Note
+    /// This makes rapid restarts reliable if the previous process has not released the address yet.
+    fn bind_tcp_retry(address: &SocketAddr) -> Result<TcpListener, Error> {
+        let (mut result, mut backoff) = (Self::bind_tcp(address), Self::BIND_BACKOFF);
+        for _ in 1..Self::BIND_ATTEMPTS {
+            // Only retry if the address is in use
+            let Err(e) = &result else {
+                break;
+            };
+            if e.kind() != ErrorKind::AddrInUse {
+                break;
+            }
+
+            // Retry after the backoff
+            let millis = backoff.as_millis();
+            error!("Address {address} is in use; retrying in {millis}ms").log_to_stderr();
+            thread::sleep(backoff);
+            (result, backoff) = (Self::bind_tcp(address), backoff.saturating_mul(2));
+        }
+
+        // Provide an actionable error if the port is privileged
+        let port = address.port();
+        match result {
+            Err(e) if e.kind() == ErrorKind::PermissionDenied && port < 1024 => {
+                let hint = "run as root, grant the `CAP_NET_BIND_SERVICE` capability, or use a port above 1023";
+                Err(error!(kind: Http, with: e, "Binding to the privileged port {port} is not permitted; {hint}"))
+            }
+            result => Ok(result?),
+        }
+    }
+
+    /// Binds a TCP listener to the given address
+    ///
+    /// # Note
+    /// IPv6 wildcard addresses are explicitly bound in dual-stack mode, so that IPv4 clients can connect regardless of
+    /// the platform's `IPV6_V6ONLY` default. All other addresses are bound via `std`, which sets `SO_REUSEADDR` on Unix
+    /// too.
+    fn bind_tcp(address: &SocketAddr) -> io::Result<TcpListener> {
+        let SocketAddr::V6(address_v6) = address else {
+            return TcpListener::bind(address);
+        };
+        let true = address_v6.ip().is_unspecified() else {
+            return TcpListener::bind(address);
+        };
+
+        // Create a dual-stack socket
+        let socket = Socket::new(Domain::IPV6, Type::STREAM, Some(Protocol::TCP))?;
+        socket.set_only_v6(false)?;
+        #[cfg(unix)]
+        socket.set_reuse_address(true)?;
+
+        // Bind the socket
+        socket.bind(&SockAddr::from(*address))?;
+        socket.listen(Self::BACKLOG)?;
+        Ok(socket.into())
+    }
+
+    /// Accepts incoming connections with the given timeouts and passes them to the dispatcher as `(rx, tx, peer)`-tuple
+    ///
+    /// # Note
+    /// The peer address is only available for TCP connections. If a TLS config is given, the connections are wrapped
+    /// in TLS; the handshake is performed lazily on the first read or write, so it does not block the accepting thread.
+    pub fn serve<F>(&self, timeout: Duration, tls: Option<&Arc<ServerConfig>>, dispatch: F) -> Result<Infallible, Error>
+    where
+        F: Fn(Stream, Stream, Option<SocketAddr>),
+    {
+        Self::serve_with(|| self.accept(), timeout, tls, dispatch)
+    }
+
+    /// Accepts incoming connections from the given accept function and passes them to the dispatcher
+    ///
+    /// # Note
+    /// After a failed accept, the next accept is delayed with exponential backoff, so that a persistent failure (e.g.
+    /// if the process has run out of file descriptors) does not spin; the backoff is reset after a successful accept.
+    fn serve_with<A, F>(mut accept: A, timeout: Duration, tls: Option<&Arc<ServerConfig>>, dispatch: F) -> !
+    where
+        A: FnMut() -> Result<(Stream, Option<SocketAddr>), Error>,
+        F: Fn(Stream, Stream, Option<SocketAddr>),
+    {
+        let mut backoff = Duration::ZERO;
+        loop {
+            // Accept the next connection; errors only affect this connection, so log them and keep serving
+            let (rx, peer) = match accept() {
+                Ok(accepted) => {
+                    backoff = Duration::ZERO;
+                    accepted
+                }
+                Err(e) => {
+                    e.log_to_stderr();
+                    backoff = backoff.saturating_mul(2).clamp(Self::ACCEPT_BACKOFF, Self::ACCEPT_BACKOFF_MAX);
+                    thread::sleep(backoff);
+                    continue;
+                }
+            };
+
+            // Close new connections immediately while draining
+            if drain::is_draining() {
+                continue;
+            }
+
+            // Set up the connection, i.e. apply the timeouts so that stalled clients cannot occupy a connection slot
+            // indefinitely and wrap the connection in TLS if enabled
+            let (rx, tx) = match Self::prepare(rx, timeout, tls) {
+                Ok(streams) => streams,
+                Err(e) => {
+                    e.log_to_stderr();
+                    continue;
+                }
+            };
+
+            // Dispatch the connection
+            dispatch(rx, tx, peer);
+        }
+    }
+
+    /// Applies the timeouts to a new connection, wraps it in TLS if enabled and returns the read and write halves
+    fn prepare(rx: Stream, timeout: Duration, tls: Option<&Arc<ServerConfig>>) -> Result<(Stream, Stream), Error> {
+        rx.set_timeouts(timeout)?;
+        let rx = match tls {
+            Some(tls) => rx.into_tls(tls)?,
+            None => rx,
+        };
+        let tx = rx.try_clone()?;
+        Ok((rx, tx))
+    }
+
+    /// Accepts the next incoming connection together with the peer address if any
+    fn accept(&self) -> Result<(Stream, Option<SocketAddr>), Error> {
+        match self {
+            Self::Tcp(listener) => {
+                let (stream, peer) = listener.accept()?;
+                Ok((Stream::Tcp(stream), Some(peer)))
+            }
+            #[cfg(unix)]
+            Self::Unix(listener) => Ok((Stream::Unix(listener.listener.accept()?.0), None)),
+        }
+    }
+}
+
+/// The socket files of all bound Unix domain socket listeners
+#[derive(Debug, Default)]
+pub struct SocketFiles(Mutex<Vec<PathBuf>>);
+impl SocketFiles {
+    /// Registers the socket file of the given listener if any, so that it is removed on shutdown
+    pub fn register(&self, listener: &Listener) {
+        #[cfg(unix)]
+        if let Listener::Unix(listener) = listener {
+            let mut paths = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+            paths.push(listener.path.clone());
+        }
+    }
+
+    /// Removes all registered socket files
+    ///
+    /// # Note
+    /// This is called on shutdown, as exiting the process skips the destructors of the listeners.
+    pub fn remove_all(&self) {
+        let paths = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        for path in paths.iter() {
+            // Best-effort to remove the socket file
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// An incoming HTTP connection
+#[derive(Debug)]
+pub enum Stream {
+    /// A TCP connection
+    Tcp(TcpStream),
+    /// A Unix domain socket connection
+    #[cfg(unix)]
+    Unix(UnixStream),
+    /// A TLS connection over an underlying connection
+    ///
+    /// # Note
+    /// The TLS session state is shared between the read and the write half, so both halves are synchronized.
+    Tls(Arc<Mutex<StreamOwned<ServerConnection, Stream>>>),
+}
+impl Stream {
+    /// Wraps the connection in a server-side TLS session with the given config
+    pub fn into_tls(self, config: &Arc<ServerConfig>) -> Result<Self, Error> {
+        let connection = ServerConnection::new(config.clone())?;
+        let stream = StreamOwned::new(connection, self);
+        Ok(Self::Tls(Arc::new(Mutex::new(stream))))
+    }
+
+    /// Whether the connection is wrapped in TLS
+    pub const fn is_tls(&self) -> bool {
+        matches!(self, Self::Tls(_))
+    }
+
+    /// Sets the read and write timeouts for the connection
+    pub fn set_timeouts(&self, timeout: Duration) -> Result<(), Error> {
+        match self {
+            Self::Tcp(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+            }
+            #[cfg(unix)]
+            Self::Unix(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+            }
+            Self::Tls(stream) => {
+                let stream = stream.lock().unwrap_or_else(PoisonError::into_inner);
+                stream.sock.set_timeouts(timeout)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates a new independently owned handle to the underlying connection
+    pub fn try_clone(&self) -> Result<Self, Error> {
+        match self {
+            Self::Tcp(stream) => Ok(Self::Tcp(stream.try_clone()?)),
+            #[cfg(unix)]
+            Self::Unix(stream) => Ok(Self::Unix(stream.try_clone()?)),
+            Self::Tls(stream) => Ok(Self::Tls(stream.clone())),
+        }
+    }
+}
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.read(buf),
+            Self::Tls(stream) => stream.lock().unwrap_or_else(PoisonError::into_inner).read(buf),
+        }
+    }
+}
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.write(buf),
+            Self::Tls(stream) => stream.lock().unwrap_or_else(PoisonError::into_inner).write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.flush(),
+            Self::Tls(stream) => stream.lock().unwrap_or_else(PoisonError::into_inner).flush(),
+        }
+    }
+}
+
+/// A Unix domain socket listener that removes its socket file if it is dropped
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct UnixSocketListener {
+    /// The underlying listener
+    listener: UnixListener,
+    /// The socket file path
+    path: PathBuf,
+}
+#[cfg(unix)]
+impl UnixSocketListener {
+    /// Binds a new Unix domain socket listener to the given path
+    ///
+    /// # Note
+    /// If the path points to a stale socket file (e.g. from a previous run that did not shut down cleanly), the stale
+    /// socket file is removed first. Other file types are never removed.
+    pub fn bind(path: &Path) -> Result<Self, Error> {
+        // Remove stale socket file if any
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            if metadata.file_type().is_socket() {
+                fs::remove_file(path)?;
+            }
+        }
+
+        // Bind the listener
+        let listener = UnixListener::bind(path)?;
+        Ok(Self { listener, path: path.to_path_buf() })
+    }
+}
+#[cfg(unix)]
+impl Drop for UnixSocketListener {
+    fn drop(&mut self) {
+        // Best-effort to remove the socket file
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::{Listener, SocketFiles};
+    use crate::config::Listen;
+    use crate::error::Error;
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use std::{env, process, thread};
+
+    /// Registered socket files are removed on shutdown even if the listener is never dropped
+    #[test]
+    fn socket_files_remove_all() -> Result<(), Error> {
+        let path = env::temp_dir().join(format!("rtsp2hls-test-{}-socket", process::id()));
+        let listener = Listener::bind(&Listen::Unix(path.clone()))?;
+        let socket_files = SocketFiles::default();
+        socket_files.register(&listener);
+        assert!(path.exists());
+
+        socket_files.remove_all();
+        assert!(!path.exists());
+        drop(listener);
+        Ok(())
+    }
+
+    /// A listener that always fails to accept is retried with backoff instead of spinning
+    #[test]
+    fn serve_accept_backoff() -> Result<(), Error> {
+        // A non-blocking listener without pending connections always fails with `WouldBlock`
+        let tcp_listener = TcpListener::bind("127.0.0.1:0")?;
+        tcp_listener.set_nonblocking(true)?;
+        let listener = Listener::Tcp(tcp_listener);
+
+        // Serve in the background and count the accept attempts
+        // Note: The serving thread never returns, so it is left behind until the test process exits
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counter = attempts.clone();
+        thread::spawn(move || {
+            let accept = || {
+                counter.fetch_add(1, Ordering::Relaxed);
+                listener.accept()
+            };
+            Listener::serve_with(accept, Duration::from_secs(1), None, |_, _, _| ())
+        });
+
+        // The backoff doubles from 10ms, so there are only a few attempts within the first second
+        thread::sleep(Duration::from_secs(1));
+        let attempts = attempts.load(Ordering::Relaxed);
+        assert!((2..=10).contains(&attempts), "{attempts}");
+        Ok(())
+    }
+}
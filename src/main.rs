@@ -13,45 +13,368 @@
 #![warn(clippy::allow_attributes_without_reason)]
 #![warn(clippy::cognitive_complexity)]
 
-use crate::config::Config;
-use crate::error::Error;
-use crate::rtsp::RtspClient;
-use ehttpd::http::{Response, ResponseExt};
-use ehttpd::Server;
+use crate::config::{Config, RootRoute};
+use crate::error::{Error, ErrorKind};
+use crate::listener::Listener;
+use crate::rtsp::{RtspClient, Watchdog};
+use crate::server::Server;
+use crate::state::State;
+use ehttpd::http::{Request, RequestExt, Response, ResponseExt};
 use std::convert::Infallible;
-use std::{process, thread};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{env, fs, process, thread};
 
+mod admin;
+mod assets;
+mod byterange;
+mod clock;
 mod config;
+mod disk;
+mod drain;
 mod error;
+mod headers;
 mod hls;
+mod listener;
+mod memory;
+mod playlist;
+mod probe;
+mod ratelimit;
+mod redirect;
 mod rtsp;
+mod sanitize;
+mod server;
+mod snapshot;
+mod sources;
+mod state;
+mod stats;
+mod status;
+mod throttle;
+mod tls;
+mod uring;
+mod viewers;
+
+/// The polling interval while waiting for the drain signal and the in-flight connections
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
 
 /// The rtsp2hls app runloop
-fn rtsp2hls(config: Config) -> Result<Infallible, Error> {
-    // Initialize the RTSP client
-    let rtsp_client = RtspClient::new(&config)?;
-    thread::spawn(move || rtsp_client.start_watchdog());
+fn rtsp2hls(config: Config, state: Arc<State>) -> Result<Infallible, Error> {
+    // Print the pipelines instead of running them in dry-run mode
+    if config.RTSP2HLS_DRYRUN {
+        for source in &config.RTSP2HLS_SOURCES {
+            println!("{}", RtspClient::command_line(&config, source));
+        }
+        process::exit(0);
+    }
+
+    // Load the TLS certificate if configured, so that an invalid certificate fails before any worker is spawned
+    let tls = match (&config.RTSP2HLS_TLSCERT, &config.RTSP2HLS_TLSKEY) {
+        (Some(cert), Some(key)) => Some(tls::load(cert, key).map_err(|e| e.with_kind(ErrorKind::Config))?),
+        _ => None,
+    };
+
+    // Warn if io_uring is enabled but cannot be used
+    if config.RTSP2HLS_IO_URING && !uring::is_available() {
+        eprintln!("io_uring is not available; falling back to blocking fragment reads");
+    }
+
+    // Give the network and the cameras time to become ready if configured
+    if !config.RTSP2HLS_STARTDELAY.is_zero() {
+        eprintln!("Delaying startup by {}s", config.RTSP2HLS_STARTDELAY.as_secs());
+        thread::sleep(config.RTSP2HLS_STARTDELAY);
+    }
+
+    // Initialize the RTSP clients
+    // Note: The unique temp directory has already been created exclusively while loading the configuration
+    fs::create_dir_all(&config.RTSP2HLS_TEMPDIR)?;
+    let mut rtsp_clients = Vec::new();
+    for source in &config.RTSP2HLS_SOURCES {
+        rtsp_clients.push(RtspClient::new(&config, &state, source)?);
+    }
+    if let Some(timeout) = config.RTSP2HLS_SELFTEST {
+        // Ensure the pipelines work before we start serving
+        for rtsp_client in &mut rtsp_clients {
+            rtsp_client.selftest(timeout)?;
+        }
+    }
+    for source in &config.RTSP2HLS_SOURCES {
+        // Discover the media properties in the background
+        probe::start(source, &state);
+    }
+    let watchdog = Watchdog::new(&config, &state, rtsp_clients);
+    thread::spawn(move || watchdog.start());
+    sources::start(&config, &state);
 
     // Initialize HTTP server with connection callback
-    let hls_server_listen = config.RTSP2HLS_LISTEN;
-    let hls_server = Server::with_request_response(config.RTSP2HLS_MAXCONN, move |request| {
-        match (request.method.as_ref(), request.target.as_ref()) {
-            (b"GET" | b"HEAD", target) if target.ends_with(b".ts") => hls::get_fragment(&request, &config),
-            (b"GET" | b"HEAD", b"/") => Response::new_307_temporaryredirect(b"/index.m3u8"),
-            (b"GET" | b"HEAD", b"/index.m3u8") => hls::get_index(&request, &config),
-            (b"GET" | b"HEAD", _) => Response::new_404_notfound(),
-            (_, _) => Response::new_405_methodnotallowed(),
+    let hls_server_listen = config.RTSP2HLS_LISTEN.clone();
+    let hls_server_timeout = config.RTSP2HLS_TIMEOUT;
+    let drain_timeout = config.RTSP2HLS_DRAINTIMEOUT;
+    let max_workers = config.RTSP2HLS_WORKERS.unwrap_or(config.RTSP2HLS_MAXCONN);
+    let max_connections = config.RTSP2HLS_MAXCONN;
+    let redirect_listen = config.RTSP2HLS_HTTPREDIRECT.clone();
+    let https_port = redirect::https_port(&config);
+    let server_state = state.clone();
+    let hls_server = Server::new(max_connections, max_workers, move |request, peer| {
+        server_state.stats.record_request();
+        let client = client_address(&request, peer, config.RTSP2HLS_TRUSTPROXY);
+        let access = config.RTSP2HLS_ACCESSLOG.then(|| (request.method.clone(), request.target.clone(), client));
+
+        // Reject the request if the client has exceeded the rate limit
+        let mut response = match config.RTSP2HLS_RATELIMIT.map(|rate| server_state.ratelimit.admit(client, rate)) {
+            Some(Err(retry_after)) => ratelimit::too_many_requests(retry_after),
+            _ => handle_request(request, peer, &config, &server_state),
+        };
+
+        // Identify the server if enabled
+        if config.RTSP2HLS_SERVERHEADER {
+            response.set_field("Server", concat!("rtsp2hls/", env!("CARGO_PKG_VERSION")));
+        }
+
+        // Log the request if enabled
+        if let Some((method, target, client)) = access {
+            log_access(&method, &target, &response, client);
         }
+        response
     });
 
-    // Start and monitor the HLS server task
-    let Err(e) = hls_server.accept(hls_server_listen);
-    Err(error!(with: e, "server task failed"))
+    // Bind all listeners first to fail fast if any address is unavailable
+    let mut listeners = Vec::new();
+    for listen in &hls_server_listen {
+        let listener =
+            Listener::bind(listen).map_err(|e| error!(kind: Http, "Failed to bind to {listen}: {}", e.error))?;
+        state.socket_files.register(&listener);
+        listeners.push(listener);
+    }
+    let mut redirect_listener = None;
+    if let (Some(listen), Some(port)) = (&redirect_listen, https_port) {
+        let listener =
+            Listener::bind(listen).map_err(|e| error!(kind: Http, "Failed to bind to {listen}: {}", e.error))?;
+        state.socket_files.register(&listener);
+        redirect_listener = Some((listener, port));
+    }
+
+    // Accept and dispatch incoming connections on all listeners
+    let hls_server = Arc::new(hls_server);
+    let (error_tx, error_rx) = mpsc::channel();
+    for listener in listeners {
+        let (hls_server, error_tx, tls) = (hls_server.clone(), error_tx.clone(), tls.clone());
+        thread::spawn(move || {
+            let Err(e) = listener.serve(hls_server_timeout, tls.as_ref(), |rx, tx, peer| {
+                if let Err(e) = hls_server.dispatch(rx, tx, peer) {
+                    error!("failed to dispatch connection: {}", e.error).log_to_stderr();
+                }
+            });
+            let _ = error_tx.send(e);
+        });
+    }
+
+    // Redirect plain HTTP to HTTPS if enabled
+    if let Some((listener, port)) = redirect_listener {
+        let handler = move |request: Request, _: Option<SocketAddr>| redirect::to_https(&request, port);
+        let redirect_server = Arc::new(Server::new(max_connections, max_workers, handler));
+        let error_tx = error_tx.clone();
+        thread::spawn(move || {
+            let Err(e) = listener.serve(hls_server_timeout, None, |rx, tx, peer| {
+                if let Err(e) = redirect_server.dispatch(rx, tx, peer) {
+                    error!("failed to dispatch connection: {}", e.error).log_to_stderr();
+                }
+            });
+            let _ = error_tx.send(e);
+        });
+    }
+    drop(error_tx);
+
+    // Monitor the server tasks until the server is drained
+    drain::install();
+    while !drain::is_draining() {
+        match error_rx.recv_timeout(DRAIN_POLL_INTERVAL) {
+            Ok(e) => return Err(error!(kind: Http, "server task failed: {}", e.error)),
+            Err(RecvTimeoutError::Timeout) => continue,
+            // This can only happen if there are no listeners at all
+            Err(RecvTimeoutError::Disconnected) => return Err(error!(kind: Http, "No listen address configured")),
+        }
+    }
+
+    // Wait for the in-flight connections, and let the watchdog terminate the workers and exit the process afterwards
+    await_connections(&*hls_server, drain_timeout);
+    state.supervision.request_shutdown();
+    let Ok(e) = error_rx.recv() else {
+        // The listeners are never dropped, so this cannot happen
+        return Err(error!(kind: Http, "No listen address configured"));
+    };
+    Err(error!(kind: Http, "server task failed: {}", e.error))
+}
+
+/// Waits until all connections of the given server are closed or the given timeout has elapsed
+fn await_connections<T>(server: &Server<T>, timeout: Duration) {
+    eprintln!("Draining {} connections", server.connections());
+    let deadline = Instant::now().checked_add(timeout);
+    while server.connections() > 0 && deadline.is_none_or(|deadline| Instant::now() < deadline) {
+        thread::sleep(DRAIN_POLL_INTERVAL);
+    }
+
+    // The remaining connections are closed once the process exits
+    let remaining = server.connections();
+    if remaining > 0 {
+        error!("Drain timeout elapsed; closing {remaining} remaining connections").log_to_stderr();
+    }
+}
+
+/// Handles an HTTP request
+fn handle_request(mut request: Request, peer: Option<SocketAddr>, config: &Config, state: &State) -> Response {
+    // Reject suspicious requests if enabled
+    if config.RTSP2HLS_STRICT_HTTP {
+        if let Err(response) = sanitize::check_request(&request) {
+            return response;
+        }
+    }
+
+    // Strip the path prefix from the request target and split off the query if any
+    let Some(target) = request.target.strip_prefix(config.RTSP2HLS_PATHPREFIX.as_bytes()) else {
+        return not_found(config);
+    };
+    let mut target = target.splitn(2, |byte| *byte == b'?');
+    let (path, query) = (target.next().unwrap_or_default(), target.next().unwrap_or_default().to_vec());
+    request.target = path.to_vec().into();
+
+    // Route the request
+    match (request.method.as_ref(), request.target.as_ref()) {
+        (b"GET" | b"HEAD", b"/crossdomain.xml") => assets::get_crossdomain(),
+        (b"GET" | b"HEAD", b"/favicon.ico") => assets::get_favicon(),
+        (b"GET" | b"HEAD", b"/index.html") => assets::get_portal(config, state),
+        (b"GET" | b"HEAD", b"/status") => status::get_status(config, state),
+        (b"GET" | b"HEAD", b"/metrics") => status::get_metrics(config, state),
+        (b"POST", b"/admin/purge") => admin::post_purge(&request, config, state),
+        (_, b"/admin/purge") => method_not_allowed("POST"),
+        (b"POST", b"/admin/reload") => admin::post_reload(&request, config, state),
+        (_, b"/admin/reload") => method_not_allowed("POST"),
+        (b"GET" | b"HEAD", b"/admin/config") => admin::get_config(&request, config),
+        (_, b"/admin/config") => method_not_allowed("GET, HEAD"),
+        _ => handle_stream_request(request, &query, peer, config, state),
+    }
+}
+
+/// Handles an HTTP request for a stream
+fn handle_stream_request(
+    mut request: Request,
+    query: &[u8],
+    peer: Option<SocketAddr>,
+    config: &Config,
+    state: &State,
+) -> Response {
+    // Select the stream via its path and strip the stream path from the request target
+    let selected = sources::current(config, state).into_iter().find_map(|source| {
+        let target = request.target.strip_prefix(source.path.as_bytes())?;
+        target.starts_with(b"/").then(|| (source, target.to_vec()))
+    });
+    let Some((source, target)) = selected else {
+        return not_found(config);
+    };
+    request.target = target.into();
+
+    // Route the request
+    let extension = config.RTSP2HLS_CONTAINER.extension().as_bytes();
+    match (request.method.as_ref(), request.target.as_ref()) {
+        (b"GET" | b"HEAD", target) if target.ends_with(extension) => {
+            hls::get_fragment(&request, peer, &source, config, state)
+        }
+        (b"GET" | b"HEAD", b"/") => match config.RTSP2HLS_ROOT {
+            RootRoute::Redirect => {
+                let index_location = source.playlist_url(&config.RTSP2HLS_PATHPREFIX);
+                Response::new_307_temporaryredirect(index_location)
+            }
+            RootRoute::Player => assets::get_player(&source, config),
+            RootRoute::NotFound => Response::new_404_notfound(),
+        },
+        (b"GET" | b"HEAD", target) if target.strip_prefix(b"/") == Some(source.playlist_name.as_bytes()) => {
+            hls::get_index(&request, query, &source, config, state)
+        }
+        (b"GET" | b"HEAD", b"/init.mp4") => hls::get_init(&source, config, state),
+        (b"GET" | b"HEAD", b"/info") => hls::get_info(&source, config, state),
+        (b"GET" | b"HEAD", b"/snapshot.jpg") => hls::get_snapshot(&source, config, state),
+        (b"GET" | b"HEAD", b"/player.html") => assets::get_player(&source, config),
+        (b"GET" | b"HEAD", _) => not_found(config),
+        (_, _) => method_not_allowed("GET, HEAD"),
+    }
+}
+
+/// Creates a `404 Not Found` response for an unknown route, with the configured body if any
+fn not_found(config: &Config) -> Response {
+    let mut response = Response::new_404_notfound();
+    if let Some(body) = &config.RTSP2HLS_NOTFOUNDBODY {
+        response.set_body_data(body.as_bytes().to_vec());
+        response.set_content_type(config.RTSP2HLS_NOTFOUNDTYPE.to_string());
+    }
+    response
+}
+
+/// Creates a `405 Method Not Allowed` response with the given permitted methods
+fn method_not_allowed(allow: &'static str) -> Response {
+    let mut response = Response::new_405_methodnotallowed();
+    response.set_field("Allow", allow);
+    response
+}
+
+/// Logs an HTTP request and its response to stdout
+fn log_access(method: &[u8], target: &[u8], response: &Response, client: Option<IpAddr>) {
+    // Get the amount of body bytes to send; the body of HEAD responses is dropped by the server
+    let is_head = method.eq_ignore_ascii_case(b"HEAD");
+    let content_length = (response.fields.iter())
+        .find(|(name, _)| name.as_ref().eq_ignore_ascii_case(b"Content-Length"))
+        .map(|(_, value)| String::from_utf8_lossy(value.as_ref()).into_owned());
+    let bytes = match (is_head, content_length) {
+        (true, _) => "0".to_string(),
+        (false, Some(content_length)) => content_length,
+        (false, None) => "-".to_string(),
+    };
+
+    // Log the request
+    let client = client.map(|client| client.to_string()).unwrap_or_else(|| "-".to_string());
+    let (method, target) = (String::from_utf8_lossy(method), String::from_utf8_lossy(target));
+    let status = String::from_utf8_lossy(response.status.as_ref());
+    println!(r#"{client} "{method} {target}" {status} {bytes}"#);
+}
+
+/// Gets the client address of a request, or the address from the `X-Forwarded-For`- or `X-Real-IP`-header if trusted
+fn client_address(request: &Request, peer: Option<SocketAddr>, trust_proxy: bool) -> Option<IpAddr> {
+    // Only trust the proxy headers if enabled, as they can be spoofed by any client
+    let peer = peer.map(|peer| peer.ip());
+    let true = trust_proxy else {
+        return peer;
+    };
+
+    // Prefer the left-most `X-Forwarded-For`-address over `X-Real-IP`
+    let forwarded_for = request.field("X-Forwarded-For").and_then(|value| headers::parse_forwarded_for(value.as_ref()));
+    let real_ip = request.field("X-Real-IP").and_then(|value| headers::parse_ip(value.as_ref()));
+    forwarded_for.or(real_ip).or(peer)
 }
 
 pub fn main() {
-    // Load config and enter server runloop
-    let Err(e) = Config::from_env().and_then(rtsp2hls);
+    // Print the version and exit if requested
+    let print_version = env::var("RTSP2HLS_PRINTVERSION").is_ok_and(|print_version| print_version == "true");
+    if print_version || env::args().skip(1).any(|argument| argument == "--version") {
+        println!("rtsp2hls {} ({})", env!("CARGO_PKG_VERSION"), env!("RTSP2HLS_GIT_HASH"));
+        process::exit(0);
+    }
+
+    // Load config
+    let config = match Config::from_env() {
+        Ok(config) => config,
+        Err(e) => {
+            e.log_to_stderr();
+            process::exit(e.kind.exit_code());
+        }
+    };
+
+    // Enter server runloop and emit the shutdown report on exit
+    let state = Arc::new(State::new(config.RTSP2HLS_TOTAL_EGRESS_BPS));
+    let shutdown_config = config.clone();
+    let Err(e) = rtsp2hls(config, state.clone());
     e.log_to_stderr();
-    process::exit(1);
+    state.stats.report(&e.error, shutdown_config.RTSP2HLS_REPORTFILE.as_deref());
+    state.socket_files.remove_all();
+    hls::remove_unique_dir(&shutdown_config);
+    process::exit(e.kind.exit_code());
 }
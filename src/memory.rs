@@ -0,0 +1,291 @@
+//! An experimental in-memory HLS segmenter
+//!
+//! In memory mode, the worker writes a continuous MPEG-TS stream to its stdout instead of writing fragment files. The
+//! stream is split into segments at the first keyframe after [`RtspClient::SEGMENT_LENGTH`], and the most recent
+//! segments are retained within a ring buffer per stream.
+
+use crate::config::{Config, Container};
+use crate::error;
+use crate::error::Error;
+use crate::rtsp::RtspClient;
+use crate::state::State;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::ffi::OsString;
+use std::io::{BufReader, ErrorKind, Read};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// The size of an MPEG-TS packet
+const PACKET_SIZE: usize = 188;
+/// The MPEG-TS sync byte
+const SYNC_BYTE: u8 = 0x47;
+/// The PID of the program association table
+const PAT_PID: u16 = 0;
+/// The amount of segments to retain beyond the playlist, so that lagging players can still fetch them
+const SPARE_SEGMENTS: usize = 2;
+
+/// The segment ring buffers per stream
+#[derive(Debug, Default)]
+pub struct Rings(Mutex<BTreeMap<String, Ring>>);
+impl Rings {
+    /// Gets the current playlist of the given stream, or `None` if there is no segment yet
+    pub fn playlist(&self, stream: &str) -> Option<String> {
+        let rings = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let ring = rings.get(stream)?;
+        let skip = ring.segments.len().saturating_sub(RtspClient::SEGMENT_COUNT);
+        let segments: Vec<&Segment> = ring.segments.iter().skip(skip).collect();
+        let first = segments.first()?;
+
+        // Compute the target duration
+        // Note: The target duration must not be smaller than any rounded segment duration (see RFC 8216,
+        // section 4.3.3.1)
+        let longest = segments.iter().map(|segment| segment.duration.as_secs_f64().round() as u64).max();
+        let target_duration = longest.unwrap_or_default().max(RtspClient::SEGMENT_LENGTH.as_secs());
+
+        // Serialize the playlist
+        let mut playlist = String::from("#EXTM3U\n#EXT-X-VERSION:3\n");
+        playlist.push_str(&format!("#EXT-X-TARGETDURATION:{target_duration}\n"));
+        playlist.push_str(&format!("#EXT-X-MEDIA-SEQUENCE:{}\n", first.sequence));
+        for segment in segments {
+            playlist.push_str(&format!("#EXTINF:{:.3},\n{}\n", segment.duration.as_secs_f64(), segment.name));
+        }
+        Some(playlist)
+    }
+
+    /// Gets the fragment with the given name of the given stream if it is still retained
+    pub fn fragment(&self, stream: &str, name: &str) -> Option<Arc<[u8]>> {
+        let rings = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let segment = rings.get(stream)?.segments.iter().find(|segment| segment.name == name)?;
+        Some(segment.data.clone())
+    }
+
+    /// Whether the fragment with the given name of the given stream has already been dropped from the ring buffer
+    pub fn is_dropped(&self, stream: &str, name: &str) -> bool {
+        let (prefix, extension) = (Container::FRAGMENT_PREFIX, Container::MpegTs.extension());
+        let Some(sequence) = name.strip_prefix(prefix).and_then(|name| name.strip_suffix(extension)) else {
+            return false;
+        };
+        let Ok(sequence) = sequence.parse::<u64>() else {
+            return false;
+        };
+
+        // Fragments that are older than the oldest retained fragment have been dropped
+        let rings = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(ring) = rings.get(stream) else {
+            return false;
+        };
+        let oldest = ring.segments.front().map_or(ring.next_sequence, |segment| segment.sequence);
+        sequence < oldest
+    }
+
+    /// Gets the wall-clock time the fragment with the given name of the given stream has been finalized at
+    pub fn finished(&self, stream: &str, name: &str) -> Option<SystemTime> {
+        let rings = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let segment = rings.get(stream)?.segments.iter().find(|segment| segment.name == name)?;
+        Some(segment.finished)
+    }
+
+    /// Gets the newest fragment of the given stream if any
+    pub fn latest(&self, stream: &str) -> Option<Arc<[u8]>> {
+        let rings = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let segment = rings.get(stream)?.segments.back()?;
+        Some(segment.data.clone())
+    }
+
+    /// Gets the names of all retained fragments of the given stream
+    pub fn fragment_names(&self, stream: &str) -> BTreeSet<OsString> {
+        let rings = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let segments = rings.get(stream).map(|ring| ring.segments.iter()).into_iter().flatten();
+        segments.map(|segment| OsString::from(&segment.name)).collect()
+    }
+
+    /// Whether the given stream has a playlist, i.e. at least one retained fragment
+    pub fn has_playlist(&self, stream: &str) -> bool {
+        let rings = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        rings.get(stream).is_some_and(|ring| !ring.segments.is_empty())
+    }
+
+    /// Drops all retained fragments of the given stream
+    ///
+    /// # Note
+    /// The media sequence continues where it left off, so players never see a media sequence number twice.
+    pub fn clear(&self, stream: &str) {
+        let mut rings = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(ring) = rings.get_mut(stream) {
+            ring.segments.clear();
+        }
+    }
+
+    /// Pushes a finalized segment into the ring buffer of the given stream and drops the oldest segments beyond its
+    /// capacity
+    fn push(&self, stream: &str, data: Vec<u8>, duration: Duration, index_width: usize) {
+        let mut rings = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let ring = rings.entry(stream.to_string()).or_default();
+
+        // Append the segment
+        let sequence = ring.next_sequence;
+        let name = Container::MpegTs.fragment_name(sequence, index_width);
+        let (finished, data) = (SystemTime::now(), Arc::from(data));
+        ring.segments.push_back(Segment { sequence, name, duration, finished, data });
+        ring.next_sequence = sequence.saturating_add(1);
+
+        // Drop the oldest segments
+        while ring.segments.len() > RtspClient::SEGMENT_COUNT.saturating_add(SPARE_SEGMENTS) {
+            ring.segments.pop_front();
+        }
+    }
+}
+
+/// A ring buffer of the most recent segments of a stream
+#[derive(Debug, Default)]
+struct Ring {
+    /// The media sequence number of the next segment
+    next_sequence: u64,
+    /// The retained segments in media sequence order
+    segments: VecDeque<Segment>,
+}
+
+/// A finalized segment
+#[derive(Debug)]
+struct Segment {
+    /// The media sequence number
+    sequence: u64,
+    /// The fragment name, e.g. `live-00000001.ts`
+    name: String,
+    /// The segment duration
+    duration: Duration,
+    /// The wall-clock time the segment has been finalized at
+    finished: SystemTime,
+    /// The MPEG-TS data
+    data: Arc<[u8]>,
+}
+
+/// The relevant fields of an MPEG-TS packet header
+#[derive(Debug)]
+struct PacketHeader {
+    /// The packet identifier
+    pid: u16,
+    /// Whether the packet starts a new PES packet or PSI section
+    payload_start: bool,
+    /// Whether the packet starts a keyframe
+    random_access: bool,
+    /// The offset of the payload after the adaptation field if any
+    payload_offset: usize,
+}
+impl PacketHeader {
+    /// Parses the header of the given packet, or returns `None` if the packet does not start with the sync byte
+    fn parse(packet: &[u8; PACKET_SIZE]) -> Option<Self> {
+        let &[SYNC_BYTE, flags, pid_low, control, adaptation_length, adaptation_flags, ..] = packet else {
+            return None;
+        };
+
+        // Parse the header fields
+        let has_adaptation = control & 0x20 != 0;
+        let random_access = has_adaptation && adaptation_length > 0 && adaptation_flags & 0x40 != 0;
+        let payload_offset = match has_adaptation {
+            true => usize::from(adaptation_length).saturating_add(5),
+            false => 4,
+        };
+        let pid = u16::from_be_bytes([flags & 0x1f, pid_low]);
+        Some(Self { pid, payload_start: flags & 0x40 != 0, random_access, payload_offset })
+    }
+}
+
+/// Segments the MPEG-TS stream from the given worker output into the ring buffer of the given stream in the background
+///
+/// # Note
+/// The thread ends once the worker closes its output. If segmenting fails, the output is closed, so the worker stalls
+/// or exits and is restarted by the watchdog; a restarted worker continues with the next media sequence number.
+pub fn start<T>(stream: &str, output: T, config: &Config, state: &Arc<State>)
+where
+    T: Read + Send + 'static,
+{
+    let (stream, config, state) = (stream.to_string(), config.clone(), state.clone());
+    thread::spawn(move || {
+        if let Err(e) = segment(&stream, output, &config, &state) {
+            error!("Failed to segment the MPEG-TS stream for {stream}: {}", e.error).log_to_stderr();
+        }
+    });
+}
+
+/// Splits the given MPEG-TS stream into segments at keyframes and pushes them into the ring buffer of the given stream
+///
+/// # Note
+/// Each segment starts with the most recent PAT and PMTs, so that each segment can be decoded on its own. Data before
+/// the first keyframe cannot be decoded and is dropped.
+fn segment<T>(stream: &str, output: T, config: &Config, state: &State) -> Result<(), Error>
+where
+    T: Read,
+{
+    let mut output = BufReader::new(output);
+    let (mut psi, mut pmt_pids) = (BTreeMap::new(), BTreeSet::new());
+    let mut current: Option<(Instant, Vec<u8>)> = None;
+    let mut packet = [0; PACKET_SIZE];
+    loop {
+        // Read the next packet
+        match output.read_exact(&mut packet) {
+            Ok(_) => (),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+        let Some(header) = PacketHeader::parse(&packet) else {
+            return Err(error!(kind: Pipeline, "Lost MPEG-TS synchronization"));
+        };
+
+        // Track the most recent program specific information
+        if let Some(pids) = (header.pid == PAT_PID).then(|| parse_pat(&packet, &header)).flatten() {
+            pmt_pids = pids;
+            psi.retain(|pid, _| *pid == PAT_PID || pmt_pids.contains(pid));
+        }
+        if header.pid == PAT_PID || pmt_pids.contains(&header.pid) {
+            psi.insert(header.pid, packet);
+        }
+
+        // Start a new segment at the first keyframe once the current segment is long enough
+        let now = Instant::now();
+        let is_due = match &current {
+            Some((started, _)) => now.duration_since(*started) >= RtspClient::SEGMENT_LENGTH,
+            None => true,
+        };
+        if header.random_access && is_due {
+            if let Some((started, data)) = current.take() {
+                state.rings.push(stream, data, now.duration_since(started), config.RTSP2HLS_INDEXWIDTH);
+            }
+            let data = psi.values().flatten().copied().collect();
+            current = Some((now, data));
+        }
+
+        // Append the packet to the current segment
+        if let Some((_, data)) = &mut current {
+            data.extend_from_slice(&packet);
+        }
+    }
+}
+
+/// Parses the PMT PIDs from the given PAT packet, or returns `None` if the packet does not start a complete PAT section
+fn parse_pat(packet: &[u8], header: &PacketHeader) -> Option<BTreeSet<u16>> {
+    let true = header.payload_start else {
+        return None;
+    };
+
+    // Locate the section after the pointer field
+    let (&pointer, payload) = packet.get(header.payload_offset..)?.split_first()?;
+    let section = payload.get(usize::from(pointer)..)?;
+    let &[_, length_high, length_low, ..] = section else {
+        return None;
+    };
+
+    // Parse the programs between the 8 byte section header and the 4 byte CRC
+    // Note: Program number `0` refers to the network information table instead of a PMT
+    let length = usize::from(u16::from_be_bytes([length_high & 0x0f, length_low]));
+    let programs = section.get(8..length.saturating_add(3).checked_sub(4)?)?;
+    let pids = (programs.chunks_exact(4))
+        .filter_map(|program| match *program {
+            [0, 0, _, _] => None,
+            [_, _, pid_high, pid_low] => Some(u16::from_be_bytes([pid_high & 0x1f, pid_low])),
+            _ => None,
+        })
+        .collect();
+    Some(pids)
+}
@@ -0,0 +1,86 @@
+//! A minimal parser for HLS media playlists
+
+use crate::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// A media segment entry within a playlist
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    /// The segment duration in seconds as specified by `#EXTINF`
+    pub duration: f64,
+    /// The segment URI
+    pub uri: String,
+}
+
+/// A parsed HLS media playlist
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Playlist {
+    /// The `#EXT-X-TARGETDURATION` value in seconds
+    pub target_duration: Option<u64>,
+    /// The `#EXT-X-MEDIA-SEQUENCE` value
+    pub media_sequence: Option<u64>,
+    /// The initialization segment URI as specified by `#EXT-X-MAP`
+    pub map: Option<String>,
+    /// The media segments in playlist order
+    pub segments: Vec<Segment>,
+}
+impl Playlist {
+    /// Loads and parses the playlist at the given path
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let text = fs::read_to_string(path)?;
+        Ok(Self::parse(&text))
+    }
+
+    /// Parses a playlist from the given text
+    ///
+    /// # Note
+    /// Unknown or malformed tags are ignored.
+    pub fn parse(text: &str) -> Self {
+        let mut playlist = Self::default();
+        let mut duration = None;
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            if let Some(value) = line.strip_prefix("#EXT-X-TARGETDURATION:") {
+                // Parse the target duration
+                playlist.target_duration = value.parse().ok();
+            } else if let Some(value) = line.strip_prefix("#EXT-X-MEDIA-SEQUENCE:") {
+                // Parse the media sequence
+                playlist.media_sequence = value.parse().ok();
+            } else if let Some(attributes) = line.strip_prefix("#EXT-X-MAP:") {
+                // Parse the initialization segment URI
+                playlist.map = quoted_attribute(attributes, "URI");
+            } else if let Some(value) = line.strip_prefix("#EXTINF:") {
+                // Parse the duration of the next segment, ignoring the optional title
+                let (value, _title) = value.split_once(',').unwrap_or((value, ""));
+                duration = value.parse().ok();
+            } else if !line.starts_with('#') {
+                // Complete the segment
+                let duration = duration.take().unwrap_or_default();
+                playlist.segments.push(Segment { duration, uri: line.to_string() });
+            }
+        }
+        playlist
+    }
+}
+
+/// Gets the value of the given quoted attribute from an attribute list, e.g. `init.mp4` for `URI` from `URI="init.mp4"`
+///
+/// # Note
+/// Quoted values that contain a comma are not supported.
+pub fn quoted_attribute(attributes: &str, name: &str) -> Option<String> {
+    (attributes.split(','))
+        .find_map(|attribute| attribute.trim().strip_prefix(name)?.strip_prefix("=\"")?.strip_suffix('"'))
+        .map(str::to_string)
+}
+
+/// Formats the value of an `#EXTINF`-tag with the given decimal precision, e.g. `#EXTINF:1.000,`
+///
+/// # Note
+/// If the duration cannot be parsed, the tag is returned unmodified.
+pub fn format_extinf(value: &str, precision: usize) -> String {
+    let (duration, title) = value.split_once(',').unwrap_or((value, ""));
+    match duration.trim().parse::<f64>() {
+        Ok(duration) => format!("#EXTINF:{duration:.precision$},{title}"),
+        Err(_) => format!("#EXTINF:{value}"),
+    }
+}
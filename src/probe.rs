@@ -0,0 +1,85 @@
+//! Media properties of the RTSP sources
+
+use crate::config::Source;
+use crate::error;
+use crate::error::Error;
+use crate::state::State;
+use std::collections::BTreeMap;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+
+/// The `gstreamer` discoverer binary
+const GST_DISCOVERER: &str = "gst-discoverer-1.0";
+/// The discovery timeout in seconds
+const TIMEOUT: &str = "10";
+
+/// The discovered media properties per stream
+#[derive(Debug, Default)]
+pub struct Probes(Mutex<BTreeMap<String, MediaInfo>>);
+impl Probes {
+    /// Gets the discovered media properties of the given stream, or the default unknown properties
+    pub fn get(&self, stream: &str) -> MediaInfo {
+        let probes = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        probes.get(stream).cloned().unwrap_or_default()
+    }
+}
+
+/// The media properties of a video stream
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MediaInfo {
+    /// The video codec, e.g. `H.264 (High Profile)`
+    pub codec: Option<String>,
+    /// The video resolution as `(width, height)`
+    pub resolution: Option<(u64, u64)>,
+    /// The video framerate as fraction, e.g. `25/1`
+    pub framerate: Option<String>,
+}
+
+/// Probes the given source once in the background
+///
+/// # Note
+/// The probe opens a separate RTSP session, so it does not delay the startup. If discovery fails, the media properties
+/// remain unknown.
+pub fn start(source: &Source, state: &Arc<State>) {
+    let (name, url, state) = (source.name.clone(), source.url.to_string(), state.clone());
+    thread::spawn(move || match discover(&url) {
+        Ok(media_info) => {
+            let mut probes = state.probes.0.lock().unwrap_or_else(PoisonError::into_inner);
+            probes.insert(name, media_info);
+        }
+        Err(e) => error!("Failed to probe the RTSP source for {name}: {}", e.error).log_to_stderr(),
+    });
+}
+
+/// Discovers the media properties of the given URL
+fn discover(url: &str) -> Result<MediaInfo, Error> {
+    let output = Command::new(GST_DISCOVERER).arg("--timeout").arg(TIMEOUT).arg(url)
+        // Capture the output
+        .stdin(Stdio::null()).stderr(Stdio::null()).output()?;
+    let true = output.status.success() else {
+        return Err(error!(kind: Pipeline, "Failed to discover media properties ({})", output.status));
+    };
+
+    // Parse the properties of the first video stream
+    let output = String::from_utf8_lossy(&output.stdout);
+    let mut lines = output.lines().map(str::trim).skip_while(|line| !line.starts_with("video #"));
+    let Some((_, codec)) = lines.next().and_then(|line| line.split_once(": ")) else {
+        return Err(error!(kind: Pipeline, "Failed to discover media properties (no video stream)"));
+    };
+
+    // Parse the video properties
+    let (mut width, mut height) = (None, None);
+    let mut media_info = MediaInfo { codec: Some(codec.to_string()), ..Default::default() };
+    for line in lines.take_while(|line| !line.starts_with("audio #") && !line.starts_with("video #")) {
+        if let Some(value) = line.strip_prefix("Width: ") {
+            width = value.parse().ok();
+        } else if let Some(value) = line.strip_prefix("Height: ") {
+            height = value.parse().ok();
+        } else if let Some(value) = line.strip_prefix("Frame rate: ") {
+            media_info.framerate = Some(value.to_string());
+        }
+    }
+    media_info.resolution = width.zip(height);
+    Ok(media_info)
+}
@@ -0,0 +1,69 @@
+//! Request rate limiting per client IP address
+//!
+//! # Token bucket
+//! Each client has a bucket that holds up to one second worth of requests and refills continuously at the configured
+//! rate, so short bursts are admitted while the sustained request rate is limited. All clients without an address
+//! (e.g. via a Unix domain socket) share a single bucket.
+
+use ehttpd::http::{Response, ResponseExt};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// The interval to evict idle buckets
+const EVICTION_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A request rate limiter with a token bucket per client address
+#[derive(Debug, Default)]
+pub struct RateLimiter(Mutex<Buckets>);
+impl RateLimiter {
+    /// Takes a token from the bucket of the given client, or returns the time until the next token is available if the
+    /// client has exceeded the given rate in requests per second
+    pub fn admit(&self, client: Option<IpAddr>, rate: u32) -> Result<(), Duration> {
+        let mut buckets = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let (now, rate) = (Instant::now(), f64::from(rate.max(1)));
+
+        // Evict idle buckets periodically, as a bucket that would be full again is indistinguishable from a new one
+        let last_eviction = buckets.last_eviction.get_or_insert(now);
+        if now.saturating_duration_since(*last_eviction) >= EVICTION_INTERVAL {
+            *last_eviction = now;
+            buckets.buckets.retain(|_, (tokens, refilled)| refill(*tokens, *refilled, now, rate) < rate);
+        }
+
+        // Refill the bucket
+        let (tokens, refilled) = buckets.buckets.entry(client).or_insert((rate, now));
+        (*tokens, *refilled) = (refill(*tokens, *refilled, now, rate), now);
+
+        // Take a token if available
+        if *tokens < 1.0 {
+            return Err(Duration::from_secs_f64((1.0 - *tokens) / rate));
+        }
+        *tokens -= 1.0;
+        Ok(())
+    }
+}
+
+/// The token buckets of all clients
+#[derive(Debug, Default)]
+struct Buckets {
+    /// The available tokens and the last refill per client address
+    buckets: BTreeMap<Option<IpAddr>, (f64, Instant)>,
+    /// The last eviction of idle buckets
+    last_eviction: Option<Instant>,
+}
+
+/// Computes the available tokens of the given bucket at the given time, capped at one second worth of requests
+fn refill(tokens: f64, refilled: Instant, now: Instant, rate: f64) -> f64 {
+    let elapsed = now.saturating_duration_since(refilled);
+    (tokens + elapsed.as_secs_f64() * rate).min(rate)
+}
+
+/// Creates a `429 Too Many Requests` response to signal that the client should retry after the given duration
+pub fn too_many_requests(retry_after: Duration) -> Response {
+    // Note: `Retry-After` has a granularity of seconds, so we round up to not invite another premature request
+    let seconds = retry_after.as_secs().saturating_add(u64::from(retry_after.subsec_nanos() > 0));
+    let mut response = Response::new_status_reason(429, "Too Many Requests");
+    response.set_field("Retry-After", seconds.max(1).to_string());
+    response
+}
@@ -0,0 +1,53 @@
+//! Redirects from plain HTTP to HTTPS
+
+use crate::config::{Config, Listen};
+use ehttpd::http::{Request, RequestExt, Response, ResponseExt};
+use std::str;
+
+/// Gets the port of the first HTTPS listener, which is the redirect target
+///
+/// # Note
+/// The config ensures that there is at least one TCP listener if redirects are enabled.
+pub fn https_port(config: &Config) -> Option<u16> {
+    config.RTSP2HLS_LISTEN.iter().find_map(|listen| match listen {
+        Listen::Tcp(address) => Some(address.port()),
+        #[cfg(unix)]
+        Listen::Unix(_) => None,
+    })
+}
+
+/// Redirects the given request to the HTTPS equivalent of the requested target on the given port
+pub fn to_https(request: &Request, port: u16) -> Response {
+    // Get the requested host name without the port
+    let host = request.field("Host").and_then(|host| host_name(host.as_ref()));
+    let target = str::from_utf8(request.target.as_ref()).ok().filter(|target| target.starts_with('/'));
+    let (Some(host), Some(target)) = (host, target) else {
+        return Response::new_status_reason(400, "Bad Request");
+    };
+
+    // Preserve the path and the query, and omit the port if it is the default port
+    let location = match port {
+        443 => format!("https://{host}{target}"),
+        port => format!("https://{host}:{port}{target}"),
+    };
+    let mut response = Response::new_status_reason(301, "Moved Permanently");
+    response.set_field("Location", location);
+    response
+}
+
+/// Gets the host name of the given `Host` header without the port, if it is a valid host name or IP literal
+fn host_name(host: &[u8]) -> Option<&str> {
+    // Strip the port, taking care of IPv6 literals
+    let host = str::from_utf8(host).ok()?.trim();
+    let name = match host.strip_prefix('[') {
+        Some(literal) => host.get(..literal.find(']')?.saturating_add(2))?,
+        None => host.split(':').next()?,
+    };
+
+    // Reject anything that could alter the location beyond the host name
+    let is_valid = |char: char| char.is_ascii_alphanumeric() || ".-_[]:".contains(char);
+    match name.chars().all(is_valid) {
+        true if !name.is_empty() => Some(name),
+        _ => None,
+    }
+}
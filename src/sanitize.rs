@@ -0,0 +1,137 @@
+//! Request sanitization to mitigate request smuggling and similar attacks
+
+use crate::error;
+use ehttpd::http::{Request, RequestExt, Response, ResponseExt};
+
+/// Rejects requests with malformed or suspicious headers with a `400 Bad Request`
+#[allow(clippy::result_large_err, reason = "The error is the `400 Bad Request` response that is sent as-is")]
+pub fn check_request(request: &Request) -> Result<(), Response> {
+    let Err(reason) = find_violation(request) else {
+        return Ok(());
+    };
+
+    // Log the rejection
+    let target = String::from_utf8_lossy(request.target.as_ref());
+    error!("Rejected suspicious request for {target:?}: {reason}").log_to_stderr();
+    Err(Response::new_status_reason(400, "Bad Request"))
+}
+
+/// Whether the given request path segment is a plain file name of at most the given length
+///
+/// # Note
+/// This rejects URL-encoded characters, path separators and parent references, so that the name can never address a
+/// file outside of its directory, regardless of any subsequent format validation.
+pub fn is_plain_file_name(name: &[u8], max_length: usize) -> bool {
+    let is_plain = |byte: &u8| byte.is_ascii_graphic() && !matches!(byte, b'%' | b'/' | b'\\');
+    let has_parent_reference = name.windows(2).any(|pair| pair == b"..");
+    !name.is_empty() && name.len() <= max_length && name.iter().all(is_plain) && !has_parent_reference
+}
+
+/// Finds the first suspicious property of the request if any
+fn find_violation(request: &Request) -> Result<(), &'static str> {
+    // Validate the request target
+    if !request.target.as_ref().iter().all(u8::is_ascii_graphic) {
+        return Err("request target contains control or non-ASCII characters");
+    }
+
+    // Validate the body framing headers
+    let content_length = request.field("Content-Length");
+    let transfer_encoding = request.field("Transfer-Encoding");
+    if content_length.is_some() && transfer_encoding.is_some() {
+        return Err("conflicting Content-Length and Transfer-Encoding headers");
+    }
+    if transfer_encoding.is_some() {
+        return Err("unsupported Transfer-Encoding header");
+    }
+    if content_length.is_some_and(|length| !length.as_ref().iter().all(u8::is_ascii_digit)) {
+        return Err("malformed Content-Length header");
+    }
+
+    // Validate that repeated `Content-Length` headers agree (see RFC 9112, section 6.3)
+    let mut content_lengths = (request.fields.iter())
+        .filter(|(name, _)| name.eq_ignore_ascii_case(b"Content-Length"))
+        .map(|(_, value)| value.as_ref());
+    if content_lengths.any(|length| Some(length) != content_length.map(AsRef::as_ref)) {
+        return Err("conflicting Content-Length headers");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::find_violation;
+    use crate::error;
+    use crate::error::Error;
+    use ehttpd::bytes::Source;
+    use ehttpd::http::Request;
+    use std::io::{BufReader, Cursor};
+
+    /// Creates a request stream with the given header fields
+    fn stream(fields: &str) -> Source {
+        let head = format!("GET /index.m3u8 HTTP/1.1\r\nHost: localhost\r\n{fields}\r\n");
+        Source::new(BufReader::new(Cursor::new(head.into_bytes())))
+    }
+
+    /// Parses a request from the given stream
+    fn request(stream: &mut Source) -> Result<Request<'_>, Error> {
+        Request::from_stream(stream)?.ok_or_else(|| error!("Missing test request"))
+    }
+
+    /// Requests with plain body framing headers are accepted
+    #[test]
+    fn find_violation_plain() -> Result<(), Error> {
+        for fields in ["", "Content-Length: 0\r\n", "Content-Length: 42\r\n"] {
+            let mut stream = stream(fields);
+            assert_eq!(find_violation(&request(&mut stream)?), Ok(()), "{fields}");
+        }
+        Ok(())
+    }
+
+    /// Requests with both a `Content-Length` and a `Transfer-Encoding` header are rejected
+    #[test]
+    fn find_violation_content_length_and_transfer_encoding() -> Result<(), Error> {
+        let mut stream = stream("Content-Length: 5\r\nTransfer-Encoding: chunked\r\n");
+        let request = request(&mut stream)?;
+        assert_eq!(find_violation(&request), Err("conflicting Content-Length and Transfer-Encoding headers"));
+        Ok(())
+    }
+
+    /// Requests with a `Transfer-Encoding` header are rejected
+    #[test]
+    fn find_violation_transfer_encoding() -> Result<(), Error> {
+        let mut stream = stream("Transfer-Encoding: chunked\r\n");
+        let request = request(&mut stream)?;
+        assert_eq!(find_violation(&request), Err("unsupported Transfer-Encoding header"));
+        Ok(())
+    }
+
+    /// Requests with a non-numeric `Content-Length` header are rejected
+    #[test]
+    fn find_violation_content_length_not_numeric() -> Result<(), Error> {
+        for length in ["12a", "-1", "+5", "0x10", "1 2"] {
+            let mut stream = stream(&format!("Content-Length: {length}\r\n"));
+            let request = request(&mut stream)?;
+            assert_eq!(find_violation(&request), Err("malformed Content-Length header"), "{length}");
+        }
+        Ok(())
+    }
+
+    /// Requests with repeated but identical `Content-Length` headers are accepted
+    #[test]
+    fn find_violation_content_length_repeated() -> Result<(), Error> {
+        let mut stream = stream("Content-Length: 5\r\nContent-Length: 5\r\n");
+        assert_eq!(find_violation(&request(&mut stream)?), Ok(()));
+        Ok(())
+    }
+
+    /// Requests with conflicting `Content-Length` headers are rejected
+    #[test]
+    fn find_violation_content_length_conflicting() -> Result<(), Error> {
+        for fields in ["Content-Length: 5\r\nContent-Length: 6\r\n", "Content-Length: 5\r\ncontent-length: 05\r\n"] {
+            let mut stream = stream(fields);
+            let request = request(&mut stream)?;
+            assert_eq!(find_violation(&request), Err("conflicting Content-Length headers"), "{fields}");
+        }
+        Ok(())
+    }
+}
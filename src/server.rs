@@ -0,0 +1,168 @@
+//! A connection-limited HTTP server that passes the peer address to the request handler
+//!
+//! # Concurrency
+//! Each connection is served by its own thread, as idle keep-alive connections and slow clients mostly wait for I/O.
+//! The amount of request handlers that run in parallel is limited separately by the amount of workers, so that the
+//! connection limit and the CPU parallelism can be tuned independently.
+
+use crate::drain;
+use crate::error;
+use crate::error::Error;
+use crate::listener::Stream;
+use ehttpd::bytes::{Sink, Source};
+use ehttpd::http::{Request, RequestExt, Response, ResponseExt};
+use std::io::{BufReader, BufWriter, Write};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread;
+
+/// A thread-per-connection HTTP server
+#[derive(Debug)]
+pub struct Server<T> {
+    /// The request handler
+    handler: T,
+    /// The amount of currently active connections
+    connections: AtomicUsize,
+    /// The maximum amount of simultaneous connections
+    max_connections: usize,
+    /// The amount of currently running request handlers
+    busy_workers: Mutex<usize>,
+    /// Signals that a worker has become idle
+    worker_idle: Condvar,
+    /// The maximum amount of request handlers that run in parallel
+    max_workers: usize,
+}
+impl<T> Server<T>
+where
+    T: Fn(Request, Option<SocketAddr>) -> Response + Send + Sync + 'static,
+{
+    /// Creates a new server with the given connection and worker limits and request handler
+    pub const fn new(max_connections: usize, max_workers: usize, handler: T) -> Self {
+        let (connections, busy_workers, worker_idle) = (AtomicUsize::new(0), Mutex::new(0), Condvar::new());
+        Self { handler, connections, max_connections, busy_workers, worker_idle, max_workers }
+    }
+
+    /// Dispatches a connection from the given peer to a new worker thread
+    pub fn dispatch(self: &Arc<Self>, rx: Stream, tx: Stream, peer: Option<SocketAddr>) -> Result<(), Error> {
+        // Reserve a connection slot
+        let reserved = self.connections.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |connections| {
+            (connections < self.max_connections).then(|| connections.saturating_add(1))
+        });
+        let Ok(_) = reserved else {
+            // Reject the connection explicitly, so that clients can distinguish an overload from an outage
+            Self::reject(tx);
+            return Err(error!("Too many simultaneous connections"));
+        };
+
+        // Serve the connection; the slot guard releases the slot even if the handler panics
+        let slot = ConnectionSlot { server: self.clone() };
+        thread::Builder::new().spawn(move || {
+            if let Err(e) = slot.server.serve(rx, tx, peer) {
+                error!("failed to serve connection: {}", e.error).log_to_stderr();
+            }
+        })?;
+        Ok(())
+    }
+
+    /// Rejects a connection with `503 Service Unavailable`
+    ///
+    /// # Note
+    /// The response is sent without reading the request, which is fine as the connection is closed afterwards. As the
+    /// response is tiny, it fits into the socket send buffer and does not block the accepting thread. TLS connections
+    /// are closed without a response, as the handshake would block the accepting thread.
+    fn reject(mut tx: Stream) {
+        if tx.is_tls() {
+            return;
+        }
+
+        let mut response: Response = Response::new_status_reason(503, "Service Unavailable");
+        response.set_field("Retry-After", "1");
+        response.set_field("Connection", "close");
+        let _ = response.to_stream(&mut tx);
+    }
+
+    /// Serves all requests of a connection
+    fn serve(&self, rx: Stream, tx: Stream, peer: Option<SocketAddr>) -> Result<(), Error> {
+        let mut rx = Source::new(BufReader::new(rx));
+        let mut tx = Sink::new(BufWriter::new(tx));
+        loop {
+            // Read the next request
+            let Some(request) = Request::from_stream(&mut rx)? else {
+                // The peer has closed the connection
+                return Ok(());
+            };
+            let close = request.field("Connection").is_some_and(|value| value.eq_ignore_ascii_case(b"close"));
+            let is_head = request.method.as_ref().eq_ignore_ascii_case(b"HEAD");
+
+            // Handle the request and drop the body for HEAD requests
+            let mut response = self.acquire_worker().run(|| (self.handler)(request, peer));
+            if is_head {
+                response.make_head();
+            }
+            if drain::is_draining() {
+                // Do not keep the connection alive while draining
+                response.set_field("Connection", "close");
+            }
+
+            // Send the response
+            response.to_stream(&mut tx)?;
+            tx.flush()?;
+            if close || response.has_connection_close() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl<T> Server<T> {
+    /// The amount of currently active connections
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    /// Waits until a worker is idle and reserves it
+    fn acquire_worker(&self) -> WorkerSlot<'_, T> {
+        let mut busy_workers = self.busy_workers.lock().unwrap_or_else(PoisonError::into_inner);
+        while *busy_workers >= self.max_workers {
+            busy_workers = self.worker_idle.wait(busy_workers).unwrap_or_else(PoisonError::into_inner);
+        }
+        *busy_workers = busy_workers.saturating_add(1);
+        WorkerSlot { server: self }
+    }
+}
+
+/// A reserved worker that is released if dropped
+#[derive(Debug)]
+struct WorkerSlot<'a, T> {
+    /// The server the worker belongs to
+    server: &'a Server<T>,
+}
+impl<T> WorkerSlot<'_, T> {
+    /// Runs the given function on the reserved worker and releases the worker afterwards
+    fn run<F, R>(self, function: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        function()
+    }
+}
+impl<T> Drop for WorkerSlot<'_, T> {
+    fn drop(&mut self) {
+        let mut busy_workers = self.server.busy_workers.lock().unwrap_or_else(PoisonError::into_inner);
+        *busy_workers = busy_workers.saturating_sub(1);
+        self.server.worker_idle.notify_one();
+    }
+}
+
+/// A reserved connection slot that is released if dropped
+#[derive(Debug)]
+struct ConnectionSlot<T> {
+    /// The server the slot belongs to
+    server: Arc<Server<T>>,
+}
+impl<T> Drop for ConnectionSlot<T> {
+    fn drop(&mut self) {
+        self.server.connections.fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,122 @@
+//! JPEG snapshots of the live stream
+
+use crate::config::{Config, Container, Source};
+use crate::error;
+use crate::error::Error;
+use crate::playlist::Playlist;
+use crate::rtsp;
+use crate::state::State;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use std::{fs, thread};
+
+/// The maximum age of a cached snapshot
+const MAX_AGE: Duration = Duration::from_secs(1);
+
+/// The most recent snapshot slot per stream directory
+#[derive(Debug, Default)]
+pub struct Snapshots(Mutex<BTreeMap<PathBuf, Arc<Mutex<Option<Snapshot>>>>>);
+
+/// A cached snapshot
+#[derive(Debug)]
+struct Snapshot {
+    /// The capture timestamp
+    captured: Instant,
+    /// The JPEG image
+    jpeg: Arc<[u8]>,
+}
+
+/// Gets a JPEG snapshot of the given HLS stream, or `None` if there is no fragment yet
+///
+/// # Note
+/// Snapshots are cached for [`MAX_AGE`]. As the snapshot slot of a stream is locked during a capture, concurrent
+/// requests are coalesced into a single capture, and all waiting requests receive its result. Each stream has its own
+/// slot, so captures of different streams run independently.
+pub fn get(source: &Source, config: &Config, state: &State) -> Result<Option<Arc<[u8]>>, Error> {
+    // Get the snapshot slot of the stream
+    // Note: The shared cache is only locked to look up the slot, so it is never locked during a capture
+    let slot = {
+        let mut cache = state.snapshots.0.lock().unwrap_or_else(PoisonError::into_inner);
+        cache.entry(source.directory.clone()).or_default().clone()
+    };
+
+    // Serve the cached snapshot if it is recent enough
+    let mut slot = slot.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(snapshot) = slot.as_ref().filter(|snapshot| snapshot.captured.elapsed() < MAX_AGE) {
+        return Ok(Some(snapshot.jpeg.clone()));
+    }
+
+    // Capture a new snapshot
+    let fragment = match config.RTSP2HLS_MEMORY {
+        true => state.rings.latest(&source.name).map(|fragment| fragment.to_vec()),
+        false => read_latest(source)?,
+    };
+    let Some(fragment) = fragment else {
+        return Ok(None);
+    };
+    let jpeg = capture(fragment, config)?;
+    *slot = Some(Snapshot { captured: Instant::now(), jpeg: jpeg.clone() });
+    Ok(Some(jpeg))
+}
+
+/// Reads the newest finalized fragment of the given stream, or `None` if there is no fragment yet
+fn read_latest(source: &Source) -> Result<Option<Vec<u8>>, Error> {
+    // Get the newest finalized fragment
+    let Ok(playlist) = Playlist::load(&source.playlist()) else {
+        return Ok(None);
+    };
+    let Some(segment) = playlist.segments.last() else {
+        return Ok(None);
+    };
+
+    // Read the fragment
+    // Note: fMP4 fragments can only be decoded together with their initialization segment
+    let mut fragment = Vec::new();
+    for uri in playlist.map.iter().chain([&segment.uri]) {
+        let true = !uri.contains(['/', '\\']) else {
+            return Err(error!("Unexpected fragment location: {uri}"));
+        };
+        fragment.extend(fs::read(source.directory.join(uri))?);
+    }
+    Ok(Some(fragment))
+}
+
+/// Decodes the first frame of the given fragment into a JPEG image
+fn capture(fragment: Vec<u8>, config: &Config) -> Result<Arc<[u8]>, Error> {
+    // Select the demuxer
+    let demuxer = match config.RTSP2HLS_CONTAINER {
+        Container::MpegTs => "tsdemux",
+        Container::Fmp4 => "qtdemux",
+    };
+
+    // Decode the first frame
+    let mut child = Command::new(rtsp::gst_launch(config)).arg("--quiet")
+        // Read and demux the fragment from stdin
+        .arg("fdsrc").arg("fd=0").arg("!").arg(demuxer)
+        // Decode the h.264 bitstream into raw frames
+        .arg("!").arg("h264parse").arg("!").arg("avdec_h264").arg("!").arg("videoconvert")
+        // Encode the first frame as JPEG and write it to stdout
+        .arg("!").arg("jpegenc").arg("snapshot=true").arg("!").arg("fdsink").arg("fd=1")
+        // Capture the output
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::null()).spawn()?;
+
+    // Feed the fragment concurrently to avoid a deadlock if the pipes are full
+    // Note: The decoder exits after the first frame, so it is fine if not all data can be written
+    let mut stdin = child.stdin.take().ok_or_else(|| error!("Failed to open snapshot decoder input"))?;
+    let feeder = thread::spawn(move || stdin.write_all(&fragment));
+    let output = child.wait_with_output()?;
+    let _ = feeder.join();
+
+    // Validate the output
+    let true = output.status.success() else {
+        return Err(error!(kind: Pipeline, "Failed to capture snapshot ({})", output.status));
+    };
+    let false = output.stdout.is_empty() else {
+        return Err(error!(kind: Pipeline, "Failed to capture snapshot (empty image)"));
+    };
+    Ok(Arc::from(output.stdout))
+}
@@ -0,0 +1,76 @@
+//! Runtime registry of the RTSP sources
+
+use crate::config::{Config, Source};
+use crate::error;
+use crate::state::State;
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+/// The polling interval of the sources directory
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The current sources if they have been reloaded from the sources directory at runtime
+#[derive(Debug, Default)]
+pub struct Registry(Mutex<Option<Vec<Source>>>);
+
+/// Gets the current sources
+///
+/// # Note
+/// These are the sources from the config, unless the sources directory has changed at runtime.
+pub fn current(config: &Config, state: &State) -> Vec<Source> {
+    let current = state.sources.0.lock().unwrap_or_else(PoisonError::into_inner);
+    current.clone().unwrap_or_else(|| config.RTSP2HLS_SOURCES.clone())
+}
+
+/// Starts the supervisor that polls the sources directory if configured
+pub fn start(config: &Config, state: &Arc<State>) {
+    let Some(sources_dir) = config.RTSP2HLS_SOURCESDIR.clone() else {
+        return;
+    };
+
+    // Poll the sources directory in the background
+    let (config, state) = (config.clone(), state.clone());
+    thread::spawn(move || {
+        let mut last_error = None;
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            let sources = Source::from_directory(&sources_dir, &config.RTSP2HLS_TEMPDIR, &config.RTSP2HLS_PLAYLISTNAME);
+            match sources {
+                Ok(sources) => {
+                    last_error = None;
+                    update(&config, &state, sources);
+                }
+                Err(e) => {
+                    // Keep the current sources until the directory is valid again, and log each error only once
+                    let e = error!("Failed to reload the sources from {}: {}", sources_dir.display(), e.error);
+                    if last_error.as_ref() != Some(&e.error) {
+                        last_error = Some(e.error.clone());
+                        e.log_to_stderr();
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// Replaces the current sources and requests the watchdog to start, restart or stop the workers of all changed streams
+fn update(config: &Config, state: &State, sources: Vec<Source>) {
+    let mut current = state.sources.0.lock().unwrap_or_else(PoisonError::into_inner);
+    let previous = current.get_or_insert_with(|| config.RTSP2HLS_SOURCES.clone());
+
+    // Collect the names of all added, changed or removed streams
+    let changed: BTreeSet<String> = (previous.iter().filter(|source| !sources.contains(source)))
+        .chain(sources.iter().filter(|source| !previous.contains(source)))
+        .map(|source| source.name.clone())
+        .collect();
+    *current = Some(sources);
+    drop(current);
+
+    // Notify the watchdog
+    for name in changed {
+        eprintln!("The source for {name} has changed; updating the stream");
+        state.supervision.request_reload(&name);
+    }
+}
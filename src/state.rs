@@ -0,0 +1,97 @@
+//! The shared runtime state of the server
+
+use crate::byterange::Indexes;
+use crate::hls::{BlockingReloads, Listed, Resets};
+use crate::listener::SocketFiles;
+use crate::memory::Rings;
+use crate::probe::Probes;
+use crate::ratelimit::RateLimiter;
+use crate::rtsp::Supervision;
+use crate::snapshot::Snapshots;
+use crate::sources::Registry;
+use crate::stats::Stats;
+use crate::throttle::Pacer;
+use crate::viewers::Viewers;
+use std::fmt::{self, Debug, Formatter};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// The runtime state that is shared between the HTTP handlers, the watchdog and the background tasks
+///
+/// # Note
+/// The state is created once per run and passed next to the immutable config, so that the config only reflects the
+/// environment.
+#[derive(Default)]
+pub struct State {
+    /// The runtime statistics
+    pub stats: Stats,
+    /// The total egress limiter if a total egress limit is configured
+    pub egress: Option<Arc<Pacer>>,
+    /// The request rate limiter
+    pub ratelimit: RateLimiter,
+    /// The viewers per stream
+    pub viewers: Viewers,
+    /// The discovered media properties per stream
+    pub probes: Probes,
+    /// The current sources
+    pub sources: Registry,
+    /// The worker supervision requests and the stream states
+    pub supervision: Supervision,
+    /// The segment ring buffers per stream in memory mode
+    pub rings: Rings,
+    /// The oldest listed fragment per stream directory
+    pub listed: Listed,
+    /// The stream directories that are warming up after their HLS artifacts have been removed
+    pub resets: Resets,
+    /// The blocking playlist reloads in progress
+    pub blocking_reloads: BlockingReloads,
+    /// The cached snapshots per stream directory
+    pub snapshots: Snapshots,
+    /// The media file indexes per stream directory in byte-range mode
+    pub indexes: Indexes,
+    /// The socket files of the bound Unix domain socket listeners
+    pub socket_files: SocketFiles,
+    /// Whether a wall-clock jump has been detected during the last watchdog check
+    clock_skewed: AtomicBool,
+    /// Whether the filesystem of the temp directory has been critically low on space during the last watchdog check
+    disk_low: AtomicBool,
+}
+impl State {
+    /// Creates a new state with the given total egress limit in bytes per second if any
+    pub fn new(total_egress_bps: Option<u64>) -> Self {
+        Self { egress: total_egress_bps.map(|rate| Arc::new(Pacer::new(rate))), ..Default::default() }
+    }
+
+    /// Checks if a wall-clock jump has been detected recently
+    ///
+    /// # Note
+    /// While this is `true`, file modification times are unreliable, so date-based validators must not be trusted.
+    pub fn is_clock_skewed(&self) -> bool {
+        self.clock_skewed.load(Ordering::Relaxed)
+    }
+
+    /// Sets whether a wall-clock jump has been detected during the last check
+    pub fn set_clock_skewed(&self, skewed: bool) {
+        self.clock_skewed.store(skewed, Ordering::Relaxed);
+    }
+
+    /// Checks if the filesystem of the temp directory has been critically low on space during the last check
+    ///
+    /// # Note
+    /// While this is `true`, `gstreamer` cannot write new fragments, so a stall is most likely caused by the full
+    /// filesystem and not by the source.
+    pub fn is_disk_low(&self) -> bool {
+        self.disk_low.load(Ordering::Relaxed)
+    }
+
+    /// Sets whether the filesystem of the temp directory has been critically low on space during the last check
+    pub fn set_disk_low(&self, low: bool) {
+        self.disk_low.store(low, Ordering::Relaxed);
+    }
+}
+impl Debug for State {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        // Note: The state contains the buffered media data, which is not useful for debugging output
+        f.debug_struct("State").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,239 @@
+//! Runtime statistics
+
+use crate::error;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// The amount of fragments within the sliding window of the encode latency
+const LATENCY_WINDOW: usize = 30;
+
+/// The runtime statistics
+#[derive(Debug)]
+pub struct Stats {
+    /// The start time
+    started: Instant,
+    /// The total amount of served fragment responses
+    fragments_served: AtomicU64,
+    /// The total amount of served fragment bytes
+    bytes_served: AtomicU64,
+    /// The total amount of playlist requests
+    playlist_requests: AtomicU64,
+    /// The total amount of worker restarts
+    restarts: AtomicU64,
+    /// The total amount of media sequence discontinuities
+    discontinuities: AtomicU64,
+    /// The time of the last HTTP request in milliseconds since the start
+    last_request: AtomicU64,
+    /// The encode latencies of the most recent fragments per stream
+    latencies: Mutex<BTreeMap<String, VecDeque<Duration>>>,
+}
+impl Stats {
+    /// Returns the time since the start
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Records an HTTP request
+    pub fn record_request(&self) {
+        let now = u64::try_from(self.uptime().as_millis()).unwrap_or(u64::MAX);
+        self.last_request.store(now, Ordering::Relaxed);
+    }
+
+    /// Returns the time since the last HTTP request, or since the start if there was no request yet
+    pub fn idle_time(&self) -> Duration {
+        let last_request = Duration::from_millis(self.last_request.load(Ordering::Relaxed));
+        self.uptime().saturating_sub(last_request)
+    }
+
+    /// Records a served fragment response with the given body length
+    pub fn record_fragment(&self, bytes: u64) {
+        self.fragments_served.fetch_add(1, Ordering::Relaxed);
+        self.bytes_served.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Records a playlist request
+    pub fn record_playlist_request(&self) {
+        self.playlist_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a worker restart
+    pub fn record_restart(&self) {
+        self.restarts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a media sequence discontinuity
+    pub fn record_discontinuity(&self) {
+        self.discontinuities.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records the encode latency of a fragment of the given stream
+    pub fn record_latency(&self, stream: &str, latency: Duration) {
+        let mut latencies = self.latencies.lock().unwrap_or_else(PoisonError::into_inner);
+        let window = latencies.entry(stream.to_string()).or_default();
+        if window.len() >= LATENCY_WINDOW {
+            window.pop_front();
+        }
+        window.push_back(latency);
+    }
+
+    /// Returns the average and the maximum encode latency over the most recent fragments of the given stream, or `None`
+    /// if no latency has been recorded yet
+    pub fn latency(&self, stream: &str) -> Option<(Duration, Duration)> {
+        let latencies = self.latencies.lock().unwrap_or_else(PoisonError::into_inner);
+        let window = latencies.get(stream)?;
+        let max = window.iter().max()?;
+        let total: Duration = window.iter().sum();
+        let average = total.checked_div(u32::try_from(window.len()).unwrap_or(u32::MAX)).unwrap_or_default();
+        Some((average, *max))
+    }
+
+    /// Returns the total amount of served fragment responses
+    pub fn fragments_served(&self) -> u64 {
+        self.fragments_served.load(Ordering::Relaxed)
+    }
+
+    /// Returns the total amount of served fragment bytes
+    pub fn bytes_served(&self) -> u64 {
+        self.bytes_served.load(Ordering::Relaxed)
+    }
+
+    /// Returns the total amount of playlist requests
+    pub fn playlist_requests(&self) -> u64 {
+        self.playlist_requests.load(Ordering::Relaxed)
+    }
+
+    /// Returns the total amount of worker restarts
+    pub fn restarts(&self) -> u64 {
+        self.restarts.load(Ordering::Relaxed)
+    }
+
+    /// Returns the total amount of media sequence discontinuities
+    pub fn discontinuities(&self) -> u64 {
+        self.discontinuities.load(Ordering::Relaxed)
+    }
+
+    /// Emits the shutdown report with the given terminal reason to stderr, and to the given file if any
+    pub fn report(&self, reason: &str, path: Option<&Path>) {
+        let report = format!(
+            r#"{{"uptime":{},"fragmentsServed":{},"bytesServed":{},"restarts":{},"reason":"{}"}}"#,
+            self.uptime().as_secs(),
+            self.fragments_served(),
+            self.bytes_served(),
+            self.restarts(),
+            escape_json(reason.trim())
+        );
+
+        // Write the report
+        eprintln!("Shutdown report: {report}");
+        if let Some(path) = path {
+            if let Err(e) = fs::write(path, format!("{report}\n")) {
+                error!(with: e, "Failed to write shutdown report to {}", path.display()).log_to_stderr();
+            }
+        }
+    }
+}
+impl Default for Stats {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            fragments_served: AtomicU64::default(),
+            bytes_served: AtomicU64::default(),
+            playlist_requests: AtomicU64::default(),
+            restarts: AtomicU64::default(),
+            discontinuities: AtomicU64::default(),
+            last_request: AtomicU64::default(),
+            latencies: Mutex::default(),
+        }
+    }
+}
+
+/// Escapes a string for use within a JSON string literal
+pub fn escape_json(string: &str) -> String {
+    let mut escaped = String::with_capacity(string.len());
+    for char in string.chars() {
+        match char {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            char if char.is_control() => escaped.push_str(&format!("\\u{:04x}", u32::from(char))),
+            char => escaped.push(char),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape_json, Stats, LATENCY_WINDOW};
+    use crate::error::Error;
+    use std::time::Duration;
+    use std::{env, fs, process};
+
+    /// Plain strings are left untouched
+    #[test]
+    fn escape_json_plain() {
+        assert_eq!(escape_json(""), "");
+        assert_eq!(escape_json("Idle timeout elapsed: 60s (ü)"), "Idle timeout elapsed: 60s (ü)");
+    }
+
+    /// Quotes, backslashes and control characters are escaped
+    #[test]
+    fn escape_json_special() {
+        assert_eq!(escape_json(r#"a "quoted" \path"#), r#"a \"quoted\" \\path"#);
+        assert_eq!(escape_json("line\nbreak"), r"line\nbreak");
+        assert_eq!(escape_json("tab\tcr\r\u{7f}"), r"tab\u0009cr\u000d\u007f");
+    }
+
+    /// Streams without recorded latencies have no latency
+    #[test]
+    fn latency_unknown() {
+        assert_eq!(Stats::default().latency("stream"), None);
+    }
+
+    /// The average and the maximum are computed over the recorded latencies
+    #[test]
+    fn latency_average_max() {
+        let stats = Stats::default();
+        for millis in [100, 300, 200] {
+            stats.record_latency("stream", Duration::from_millis(millis));
+        }
+        assert_eq!(stats.latency("stream"), Some((Duration::from_millis(200), Duration::from_millis(300))));
+    }
+
+    /// Only the most recent latencies are taken into account
+    #[test]
+    fn latency_window() {
+        let stats = Stats::default();
+        stats.record_latency("stream", Duration::from_secs(60));
+        for _ in 0..LATENCY_WINDOW {
+            stats.record_latency("stream", Duration::from_millis(50));
+        }
+        assert_eq!(stats.latency("stream"), Some((Duration::from_millis(50), Duration::from_millis(50))));
+    }
+
+    /// The shutdown report contains the aggregated values and the escaped terminal reason
+    #[test]
+    fn report_aggregates() -> Result<(), Error> {
+        let stats = Stats::default();
+        stats.record_fragment(1024);
+        for _ in 0..2 {
+            stats.record_restart();
+        }
+
+        // Write the report
+        let path = env::temp_dir().join(format!("rtsp2hls-test-{}-report.json", process::id()));
+        stats.report("Idle \"timeout\"\n", Some(&path));
+        let report = fs::read_to_string(&path)?;
+        fs::remove_file(&path)?;
+
+        // Validate the report
+        assert!(report.starts_with(r#"{"uptime":"#), "{report}");
+        let expected = r#","fragmentsServed":1,"bytesServed":1024,"restarts":2,"reason":"Idle \"timeout\""}"#;
+        assert!(report.ends_with(&format!("{expected}\n")), "{report}");
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+//! Status and metrics request handlers
+
+use crate::config::{Config, Source};
+use crate::hls;
+use crate::sources;
+use crate::state::State;
+use crate::stats;
+use ehttpd::http::{Response, ResponseExt};
+
+/// Handles a GET request for `/status`
+pub fn get_status(config: &Config, state: &State) -> Response {
+    // Serialize the status of all streams
+    let streams: Vec<String> = (sources::current(config, state).iter())
+        .map(|source| {
+            let health = stream_health(source, state);
+            let (name, viewers) = (&source.name, state.viewers.count(&source.name));
+            let (video, latency) = (video_status(name, state), latency_status(name, state));
+            format!(r#""{name}":{{"state":"{health}","viewers":{viewers},"video":{video},"latency":{latency}}}"#)
+        })
+        .collect();
+    let status = format!(r#"{{"streams":{{{}}}}}"#, streams.join(","));
+
+    // Assemble response
+    let mut response = Response::new_200_ok();
+    response.set_body_data(status);
+    response.set_content_type("application/json");
+    response.set_field("Cache-Control", "no-cache");
+    response
+}
+
+/// Handles a GET request for `/metrics`
+pub fn get_metrics(config: &Config, state: &State) -> Response {
+    // Collect the metrics as `(name, type, help, samples)`-tuples with `(labels, value)`-samples
+    let total = |value: u64| vec![(String::new(), value)];
+    let per_stream = |value: &dyn Fn(&Source) -> u64| -> Vec<(String, u64)> {
+        (sources::current(config, state).iter())
+            .map(|source| (format!(r#"{{stream="{}"}}"#, source.name), value(source)))
+            .collect()
+    };
+    let (stats, viewers) = (&state.stats, &state.viewers);
+    let metrics = [
+        ("rtsp2hls_fragments_served_total", "counter", "Served fragments", total(stats.fragments_served())),
+        ("rtsp2hls_bytes_served_total", "counter", "Served fragment bytes", total(stats.bytes_served())),
+        ("rtsp2hls_playlist_requests_total", "counter", "Playlist requests", total(stats.playlist_requests())),
+        ("rtsp2hls_worker_restarts_total", "counter", "Worker restarts", total(stats.restarts())),
+        ("rtsp2hls_discontinuities_total", "counter", "Media sequence gaps", total(stats.discontinuities())),
+        ("rtsp2hls_stream_up", "gauge", "Whether the stream is up", per_stream(&|source| stream_up(source, state))),
+        ("rtsp2hls_viewers", "gauge", "Current viewers", per_stream(&|source| viewers.count(&source.name) as u64)),
+    ];
+
+    // Serialize the metrics in the Prometheus text exposition format
+    let mut body = String::new();
+    for (name, kind, help, samples) in metrics {
+        body.push_str(&format!("# HELP {name} {help}\n# TYPE {name} {kind}\n"));
+        for (labels, value) in samples {
+            body.push_str(&format!("{name}{labels} {value}\n"));
+        }
+    }
+
+    // Assemble response
+    let mut response = Response::new_200_ok();
+    response.set_body_data(body);
+    response.set_content_type("text/plain; version=0.0.4");
+    response.set_field("Cache-Control", "no-cache");
+    response
+}
+
+/// Serializes the discovered video properties of the given stream, using `unknown` for undiscovered properties
+fn video_status(stream: &str, state: &State) -> String {
+    let media_info = state.probes.get(stream);
+    let resolution = media_info.resolution.map(|(width, height)| format!("{width}x{height}"));
+    let [codec, resolution, framerate] = [media_info.codec, resolution, media_info.framerate]
+        .map(|value| stats::escape_json(value.as_deref().unwrap_or("unknown")));
+    format!(r#"{{"codec":"{codec}","resolution":"{resolution}","framerate":"{framerate}"}}"#)
+}
+
+/// Serializes the encode latency of the given stream in milliseconds, or `null` if it has not been measured yet
+fn latency_status(stream: &str, state: &State) -> String {
+    let Some((average, max)) = state.stats.latency(stream) else {
+        return String::from("null");
+    };
+    format!(r#"{{"averageMs":{},"maxMs":{}}}"#, average.as_millis(), max.as_millis())
+}
+
+/// Gets the health of the given stream
+///
+/// # Note
+/// The stream is `unreachable` once it has ended, `stalled` while its worker has been restarted after a failure and has
+/// not made progress yet, `warming-up` until the playlist of a new worker has been read, and `live` otherwise.
+fn stream_health(source: &Source, state: &State) -> &'static str {
+    match (state.supervision.is_unreachable(&source.name), state.supervision.is_stalled(&source.name)) {
+        (true, _) => "unreachable",
+        (false, true) => "stalled",
+        (false, false) if hls::is_warming_up(source, state) => "warming-up",
+        (false, false) => "live",
+    }
+}
+
+/// Whether the given stream is up, i.e. it is reachable and has a playlist
+fn stream_up(source: &Source, state: &State) -> u64 {
+    let has_playlist = source.playlist().is_file() || state.rings.has_playlist(&source.name);
+    let is_up = !state.supervision.is_unreachable(&source.name) && has_playlist;
+    u64::from(is_up)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::stream_health;
+    use crate::config::{Config, Source};
+    use crate::error::Error;
+    use crate::hls;
+    use crate::state::State;
+    use std::fs;
+
+    /// A reset stream is warming up until the playlist of the new worker has been read
+    #[test]
+    fn stream_health_warming_up() -> Result<(), Error> {
+        let (config, state) = (Config::for_tests()?, State::default());
+        let source = Source::for_tests("health-warming-up");
+        fs::create_dir_all(&source.directory)?;
+        assert_eq!(stream_health(&source, &state), "live");
+
+        // Reset the stream directory
+        hls::remove_artifacts(&source, &state)?;
+        assert_eq!(stream_health(&source, &state), "warming-up");
+
+        // Let the new worker write its playlist
+        fs::write(source.playlist(), "#EXTM3U\n")?;
+        hls::read_playlist(&source, &config, &state)?;
+        assert_eq!(stream_health(&source, &state), "live");
+
+        fs::remove_dir_all(&source.directory)?;
+        Ok(())
+    }
+}
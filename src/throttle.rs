@@ -0,0 +1,112 @@
+//! A shared bandwidth limiter
+
+use std::io::{self, Read};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A pacer to limit the total throughput across multiple threads
+///
+/// # Fairness
+/// Each read reserves the next free transmission slot for its chunk under a lock, and then sleeps until the slot
+/// begins. As the chunks are small and of equal size, concurrent transfers are effectively served round-robin in their
+/// reservation order, so a single client cannot starve the others.
+#[derive(Debug)]
+pub struct Pacer {
+    /// The rate in bytes per second
+    rate: u64,
+    /// The start of the next free transmission slot
+    next_slot: Mutex<Option<Instant>>,
+}
+impl Pacer {
+    /// The maximum chunk size per reservation
+    pub const CHUNK_SIZE: usize = 16 * 1024;
+
+    /// Creates a new pacer with the given rate in bytes per second
+    pub const fn new(rate: u64) -> Self {
+        Self { rate, next_slot: Mutex::new(None) }
+    }
+
+    /// Reserves the transmission of the given amount of bytes and blocks until the reserved slot begins
+    pub fn acquire(&self, amount: usize) {
+        // Compute the slot duration
+        #[allow(clippy::cast_precision_loss, reason = "The precision is sufficient for pacing")]
+        let duration = Duration::from_secs_f64(amount as f64 / self.rate.max(1) as f64);
+
+        // Reserve the next free slot
+        let slot = {
+            let mut next_slot = self.next_slot.lock().unwrap_or_else(PoisonError::into_inner);
+            let now = Instant::now();
+            let slot = next_slot.map_or(now, |next_slot| next_slot.max(now));
+            *next_slot = slot.checked_add(duration);
+            slot
+        };
+
+        // Wait for the slot to begin
+        thread::sleep(slot.saturating_duration_since(Instant::now()));
+    }
+}
+
+/// A reader that paces its reads with a pacer
+#[derive(Debug)]
+pub struct PacedReader<T> {
+    /// The underlying reader
+    inner: T,
+    /// The pacer
+    pacer: Arc<Pacer>,
+}
+impl<T> PacedReader<T> {
+    /// Creates a new paced reader
+    pub const fn new(inner: T, pacer: Arc<Pacer>) -> Self {
+        Self { inner, pacer }
+    }
+}
+impl<T> Read for PacedReader<T>
+where
+    T: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Limit the read to a single chunk
+        let chunk_size = buf.len().min(Pacer::CHUNK_SIZE);
+        let chunk = buf.get_mut(..chunk_size).unwrap_or_default();
+
+        // Read and pace the chunk
+        let amount = self.inner.read(chunk)?;
+        self.pacer.acquire(amount);
+        Ok(amount)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pacer;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// Concurrent transfers share the rate instead of each getting the full rate
+    #[test]
+    fn aggregate_throughput() {
+        const RATE: u64 = 1024 * 1024;
+        const THREADS: usize = 4;
+        const CHUNKS: usize = 4;
+
+        // Transfer the chunks concurrently
+        let pacer = Arc::new(Pacer::new(RATE));
+        let start = Instant::now();
+        let transfers: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let pacer = pacer.clone();
+                thread::spawn(move || (0..CHUNKS).for_each(|_| pacer.acquire(Pacer::CHUNK_SIZE)))
+            })
+            .collect();
+        for transfer in transfers {
+            assert!(transfer.join().is_ok(), "transfer thread panicked");
+        }
+
+        // The first chunk starts immediately, all subsequent chunks are paced at the total rate
+        let paced = (THREADS * CHUNKS - 1) * Pacer::CHUNK_SIZE;
+        let expected = Duration::from_secs_f64(paced as f64 / RATE as f64);
+        assert!(start.elapsed() >= expected, "{:?} < {expected:?}", start.elapsed());
+    }
+}
@@ -0,0 +1,39 @@
+//! TLS termination for the HLS HTTP server
+
+use crate::error;
+use crate::error::Error;
+use rustls::pki_types::CertificateDer;
+use rustls::ServerConfig;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Loads the TLS server config from the given PEM-encoded certificate chain and private key files
+///
+/// # Note
+/// The certificate file must contain the server certificate first, followed by the intermediate certificates if any.
+pub fn load(cert: &Path, key: &Path) -> Result<Arc<ServerConfig>, Error> {
+    // Read the certificate chain
+    let cert_file = File::open(cert).map_err(|e| error!(with: e, "Failed to open {}", cert.display()))?;
+    let certs: Result<Vec<CertificateDer>, _> = rustls_pemfile::certs(&mut BufReader::new(cert_file)).collect();
+    let certs = certs.map_err(|e| error!(with: e, "Failed to read {}", cert.display()))?;
+    let true = !certs.is_empty() else {
+        return Err(error!("No certificate found in {}", cert.display()));
+    };
+
+    // Read the private key
+    let key_file = File::open(key).map_err(|e| error!(with: e, "Failed to open {}", key.display()))?;
+    let private_key = rustls_pemfile::private_key(&mut BufReader::new(key_file));
+    let private_key = private_key.map_err(|e| error!(with: e, "Failed to read {}", key.display()))?;
+    let Some(private_key) = private_key else {
+        return Err(error!("No private key found in {}", key.display()));
+    };
+
+    // Create the server config
+    // Note: We only speak HTTP/1.1, so we announce it explicitly to ALPN-aware clients
+    let config = ServerConfig::builder().with_no_client_auth().with_single_cert(certs, private_key);
+    let mut config = config.map_err(|e| error!(with: e, "Invalid certificate or private key"))?;
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(Arc::new(config))
+}
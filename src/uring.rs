@@ -0,0 +1,301 @@
+//! Fragment reads via `io_uring` on Linux
+//!
+//! # Note
+//! `ehttpd` streams response bodies from blocking readers, so `io_uring` cannot take over the socket writes. Instead,
+//! the reader reads ahead: While a chunk is written to the client, the kernel already reads the next chunk into a
+//! second buffer, so that the file I/O overlaps with the socket I/O. The rings are pooled, so that their setup cost is
+//! amortized across fragments. If `io_uring` is disabled, not compiled in or not available (e.g. on kernels before 5.6
+//! or if it is blocked by a seccomp profile), fragments are read via a buffered blocking reader instead.
+
+use crate::config::Config;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+
+/// A reader for a fragment body
+#[derive(Debug)]
+pub enum FragmentReader {
+    /// A buffered blocking reader
+    Blocking(BufReader<File>),
+    /// A read-ahead reader via `io_uring`
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    Uring(Box<ring::UringReader>),
+}
+impl FragmentReader {
+    /// Creates a reader for the given file with the configured buffer size, which reads via `io_uring` if it is enabled
+    /// and available, or via a buffered blocking reader otherwise
+    pub fn new(file: File, config: &Config) -> Self {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        let file = match config.RTSP2HLS_IO_URING {
+            true => match ring::UringReader::new(file, config.RTSP2HLS_READBUF) {
+                Ok(reader) => return Self::Uring(Box::new(reader)),
+                Err(file) => file,
+            },
+            false => file,
+        };
+        Self::Blocking(BufReader::with_capacity(config.RTSP2HLS_READBUF, file))
+    }
+}
+impl Read for FragmentReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Blocking(reader) => reader.read(buf),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Self::Uring(reader) => reader.read(buf),
+        }
+    }
+}
+
+/// Whether fragments can be read via `io_uring`, i.e. the `io-uring` feature is enabled and the kernel supports it
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub fn is_available() -> bool {
+    ring::is_available()
+}
+
+/// Whether fragments can be read via `io_uring`, which is never the case without the `io-uring` feature on Linux
+#[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+pub const fn is_available() -> bool {
+    false
+}
+
+/// The `io_uring` backend
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod ring {
+    use io_uring::{opcode, types, IoUring, Probe};
+    use std::fmt::{self, Debug, Formatter};
+    use std::fs::File;
+    use std::io::{self, ErrorKind, Read};
+    use std::mem;
+    use std::ops::Range;
+    use std::os::fd::AsRawFd;
+    use std::sync::{Mutex, OnceLock, PoisonError};
+
+    /// The amount of submission queue entries per ring, as each reader has at most one read in flight
+    const ENTRIES: u32 = 2;
+    /// The maximum amount of idle rings that are kept for reuse
+    const MAX_IDLE: usize = 256;
+
+    /// The idle rings that can be reused by the next reader
+    static IDLE: Mutex<Vec<IoUring>> = Mutex::new(Vec::new());
+    /// Whether the kernel supports `io_uring` reads
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+
+    /// Whether the kernel supports `io_uring` reads, which is probed once
+    pub fn is_available() -> bool {
+        *AVAILABLE.get_or_init(|| match create() {
+            Ok(ring) => {
+                release(ring);
+                true
+            }
+            Err(_) => false,
+        })
+    }
+
+    /// Creates a new ring and ensures that it supports reads (i.e. Linux 5.6 or newer)
+    fn create() -> io::Result<IoUring> {
+        let ring = IoUring::new(ENTRIES)?;
+        let mut probe = Probe::new();
+        ring.submitter().register_probe(&mut probe)?;
+        match probe.is_supported(opcode::Read::CODE) {
+            true => Ok(ring),
+            false => Err(io::Error::from(ErrorKind::Unsupported)),
+        }
+    }
+
+    /// Takes an idle ring or creates a new one
+    fn acquire() -> io::Result<IoUring> {
+        let mut idle = IDLE.lock().unwrap_or_else(PoisonError::into_inner);
+        match idle.pop() {
+            Some(ring) => Ok(ring),
+            None => {
+                drop(idle);
+                create()
+            }
+        }
+    }
+
+    /// Keeps the given ring for reuse unless there are enough idle rings already
+    ///
+    /// # Note
+    /// The ring must not have any read in flight.
+    fn release(ring: IoUring) {
+        let mut idle = IDLE.lock().unwrap_or_else(PoisonError::into_inner);
+        if idle.len() < MAX_IDLE {
+            idle.push(ring);
+        }
+    }
+
+    /// A reader that reads the next chunk of a file ahead via `io_uring` while the current chunk is consumed
+    pub struct UringReader {
+        /// The ring, or `None` if it has been leaked as its read could not be awaited
+        ring: Option<IoUring>,
+        /// The file
+        file: File,
+        /// The buffer with the current chunk
+        ready: Box<[u8]>,
+        /// The unread range of the current chunk
+        unread: Range<usize>,
+        /// The buffer the kernel is reading the next chunk into, if a read is in flight
+        pending: Option<Box<[u8]>>,
+        /// The buffer for the first read, until it has been submitted
+        spare: Option<Box<[u8]>>,
+        /// The file offset of the next chunk
+        offset: u64,
+        /// Whether the file has been read completely or a read has failed
+        eof: bool,
+    }
+    impl UringReader {
+        /// Creates a new reader for the given file with the given chunk size, or returns the file if `io_uring` is not
+        /// available
+        pub fn new(file: File, chunk_size: usize) -> Result<Self, File> {
+            if !is_available() {
+                return Err(file);
+            }
+            let Ok(ring) = acquire() else {
+                return Err(file);
+            };
+
+            // Init self
+            let (ready, spare) = (vec![0; chunk_size].into_boxed_slice(), vec![0; chunk_size].into_boxed_slice());
+            let (unread, pending, offset, eof) = (0..0, None, 0, false);
+            Ok(Self { ring: Some(ring), file, ready, unread, pending, spare: Some(spare), offset, eof })
+        }
+
+        /// Submits a read of the next chunk into the given buffer
+        fn submit(&mut self, mut buffer: Box<[u8]>) -> io::Result<()> {
+            let ring = self.ring.as_mut().ok_or_else(|| io::Error::other("The io_uring has been released"))?;
+            let length = u32::try_from(buffer.len()).unwrap_or(u32::MAX);
+            let fd = types::Fd(self.file.as_raw_fd());
+            let read = opcode::Read::new(fd, buffer.as_mut_ptr(), length).offset(self.offset).build();
+
+            // SAFETY: The buffer is kept in `pending` and the file is owned by the reader, and the reader waits for the
+            // completion of the read before either of them is released
+            let pushed = unsafe { ring.submission().push(&read) };
+            pushed.map_err(|_| io::Error::other("The io_uring submission queue is full"))?;
+            self.pending = Some(buffer);
+            ring.submit()?;
+            Ok(())
+        }
+
+        /// Waits for the read in flight and returns its buffer and the amount of bytes read
+        fn complete(&mut self) -> io::Result<(Box<[u8]>, usize)> {
+            let ring = self.ring.as_mut().ok_or_else(|| io::Error::other("The io_uring has been released"))?;
+            let entry = loop {
+                if let Some(entry) = ring.completion().next() {
+                    break entry;
+                }
+                match ring.submit_and_wait(1) {
+                    Ok(_) => continue,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                }
+            };
+
+            // Take the filled buffer
+            let buffer = self.pending.take().ok_or_else(|| io::Error::other("Unexpected io_uring completion"))?;
+            match usize::try_from(entry.result()) {
+                Ok(length) => {
+                    let length = length.min(buffer.len());
+                    Ok((buffer, length))
+                }
+                Err(_) => Err(io::Error::from_raw_os_error(entry.result().saturating_neg())),
+            }
+        }
+    }
+    impl Read for UringReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.unread.is_empty() && !self.eof {
+                // Start the first read; afterwards, the next chunk has always been requested ahead
+                if let Some(spare) = self.spare.take() {
+                    self.submit(spare)?;
+                }
+
+                // Swap in the next chunk and read ahead into the consumed buffer
+                let (filled, length) = self.complete().inspect_err(|_| self.eof = true)?;
+                let consumed = mem::replace(&mut self.ready, filled);
+                (self.unread, self.offset) = (0..length, self.offset.saturating_add(length as u64));
+                match length {
+                    0 => self.eof = true,
+                    _ => self.submit(consumed)?,
+                }
+            }
+
+            // Copy the unread part of the current chunk
+            let unread = self.ready.get(self.unread.clone()).unwrap_or_default();
+            let amount = unread.len().min(buf.len());
+            let (Some(target), Some(source)) = (buf.get_mut(..amount), unread.get(..amount)) else {
+                return Ok(0);
+            };
+            target.copy_from_slice(source);
+            self.unread.start = self.unread.start.saturating_add(amount);
+            Ok(amount)
+        }
+    }
+    impl Debug for UringReader {
+        fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+            f.debug_struct("UringReader")
+                .field("file", &self.file)
+                .field("offset", &self.offset)
+                .finish_non_exhaustive()
+        }
+    }
+    impl Drop for UringReader {
+        fn drop(&mut self) {
+            // Wait for the read in flight, as the kernel may still write into its buffer
+            if self.pending.is_some() && self.complete().is_err() && self.pending.is_some() {
+                // The read could not be awaited, so neither its buffer nor the ring may be released
+                mem::forget(self.pending.take());
+                mem::forget(self.ring.take());
+                return;
+            }
+            if let Some(ring) = self.ring.take() {
+                release(ring);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_available, FragmentReader};
+    use crate::config::Config;
+    use crate::error::Error;
+    use std::fs::{self, File};
+    use std::io::Read;
+    use std::{env, process};
+
+    /// Reads the given data through a fragment reader with the given `io_uring` switch, and returns the read data and
+    /// whether the reader uses the blocking fallback
+    fn read_through(name: &str, data: &[u8], io_uring: bool) -> Result<(Vec<u8>, bool), Error> {
+        let mut config = Config::for_tests()?;
+        (config.RTSP2HLS_IO_URING, config.RTSP2HLS_READBUF) = (io_uring, 4096);
+        let path = env::temp_dir().join(format!("rtsp2hls-test-{}-{name}.ts", process::id()));
+        fs::write(&path, data)?;
+
+        let mut reader = FragmentReader::new(File::open(&path)?, &config);
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read)?;
+        fs::remove_file(&path)?;
+        Ok((read, matches!(reader, FragmentReader::Blocking(_))))
+    }
+
+    /// Fragments spanning several chunks are read completely, via `io_uring` if it is available, or via the blocking
+    /// fallback otherwise
+    #[test]
+    fn fragment_reader_io_uring() -> Result<(), Error> {
+        let data: Vec<u8> = (0..10_000u32).map(|index| (index % 251) as u8).collect();
+        let (read, is_blocking) = read_through("io-uring", &data, true)?;
+        assert_eq!(read, data);
+        assert_eq!(is_blocking, !is_available());
+        Ok(())
+    }
+
+    /// Fragments are read via the blocking fallback if `io_uring` is disabled
+    #[test]
+    fn fragment_reader_fallback() -> Result<(), Error> {
+        let data: Vec<u8> = (0..10_000u32).map(|index| (index % 251) as u8).collect();
+        let (read, is_blocking) = read_through("fallback", &data, false)?;
+        assert_eq!(read, data);
+        assert!(is_blocking);
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+//! Viewer accounting for the HLS streams
+//!
+//! # Heuristic
+//! A viewer is a distinct peer IP address that has fetched a fragment of a stream within the viewer window. As players
+//! fetch one fragment per segment interval, an active player is always counted; the window spans a few segment
+//! intervals so that request jitter does not make the count flap. A viewer decays once it has not fetched a fragment
+//! for a full window. All connections without a peer address (e.g. via a Unix domain socket) are counted as a single
+//! viewer.
+
+use crate::rtsp::RtspClient;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// The time after which a viewer decays if it has not fetched a fragment (currently three segment intervals)
+const WINDOW: Duration = Duration::from_secs(RtspClient::SEGMENT_LENGTH.as_secs() * 3);
+
+/// The last fragment fetch per `(stream, peer)`-pair
+#[derive(Debug, Default)]
+pub struct Viewers(Mutex<BTreeMap<(String, Option<IpAddr>), Instant>>);
+impl Viewers {
+    /// Records a fragment fetch by the given peer if the stream has capacity for it
+    ///
+    /// # Note
+    /// Existing viewers are always admitted; new viewers are rejected if the stream already has `max_viewers` viewers.
+    pub fn admit(&self, stream: &str, peer: Option<IpAddr>, max_viewers: Option<usize>) -> bool {
+        let mut viewers = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let now = Instant::now();
+        viewers.retain(|_, last_fetch| now.saturating_duration_since(*last_fetch) < WINDOW);
+
+        // Check the capacity for new viewers
+        let key = (stream.to_string(), peer);
+        if let (false, Some(max_viewers)) = (viewers.contains_key(&key), max_viewers) {
+            let count = viewers.keys().filter(|(viewer_stream, _)| viewer_stream == stream).count();
+            if count >= max_viewers {
+                return false;
+            }
+        }
+
+        // Record the fetch
+        viewers.insert(key, now);
+        true
+    }
+
+    /// Returns the amount of current viewers of the given stream
+    pub fn count(&self, stream: &str) -> usize {
+        let viewers = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let now = Instant::now();
+        (viewers.iter())
+            .filter(|((viewer_stream, _), _)| viewer_stream == stream)
+            .filter(|(_, last_fetch)| now.saturating_duration_since(**last_fetch) < WINDOW)
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Viewers;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    /// Creates a test peer address with the given last octet
+    fn peer(octet: u8) -> Option<IpAddr> {
+        Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, octet)))
+    }
+
+    /// Each distinct peer is counted once per stream, and all peers without an address count as a single viewer
+    #[test]
+    fn count_distinct_peers() {
+        let viewers = Viewers::default();
+        for peer in [peer(1), peer(2), peer(1), None, None] {
+            assert!(viewers.admit("front", peer, None));
+        }
+        assert!(viewers.admit("back", peer(1), None));
+
+        assert_eq!(viewers.count("front"), 3);
+        assert_eq!(viewers.count("back"), 1);
+        assert_eq!(viewers.count("other"), 0);
+    }
+
+    /// New viewers are rejected once the stream is at capacity, while existing viewers and other streams are admitted
+    #[test]
+    fn admit_viewer_limit() {
+        let viewers = Viewers::default();
+        assert!(viewers.admit("front", peer(1), Some(2)));
+        assert!(viewers.admit("front", peer(2), Some(2)));
+        assert!(!viewers.admit("front", peer(3), Some(2)));
+
+        assert!(viewers.admit("front", peer(1), Some(2)));
+        assert!(viewers.admit("back", peer(3), Some(2)));
+        assert_eq!(viewers.count("front"), 2);
+    }
+}
@@ -0,0 +1,153 @@
+//! End-to-end tests against a synthetic RTSP source
+//!
+//! # Note
+//! These tests require `gstreamer` and the `test-launch` example server of `gst-rtsp-server` in `PATH`, and are thus
+//! only compiled with the `e2e` feature, e.g. `cargo test --features=e2e`.
+#![cfg(feature = "e2e")]
+
+use std::error::Error;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process::{self, Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use std::{env, fs, thread};
+
+/// The `videotestsrc`-backed RTSP test stream served by `test-launch` at `/test`
+const TEST_PIPELINE: &str = "( videotestsrc is-live=true ! video/x-raw,width=320,height=240,framerate=25/1 \
+    ! x264enc tune=zerolatency speed-preset=ultrafast key-int-max=25 ! rtph264pay name=pay0 pt=96 )";
+/// The time to wait for the first playlist with a fragment
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+/// The polling interval while waiting for the first playlist
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A running RTSP test server and an `rtsp2hls` instance streaming from it
+struct Harness {
+    /// The RTSP test server process
+    rtsp_server: Child,
+    /// The `rtsp2hls` process
+    rtsp2hls: Child,
+    /// The HTTP port of `rtsp2hls`
+    http_port: u16,
+    /// The temp directory of `rtsp2hls`
+    tempdir: PathBuf,
+}
+impl Harness {
+    /// Starts the RTSP test server and an `rtsp2hls` instance streaming from it
+    fn start() -> Result<Self, Box<dyn Error>> {
+        // Use a distinct temp directory per test
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let counter = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let tempdir = env::temp_dir().join(format!("rtsp2hls-e2e-{}-{counter}", process::id()));
+        fs::create_dir_all(&tempdir)?;
+
+        // Start the RTSP test server
+        let rtsp_port = free_port()?;
+        let rtsp_server = Command::new("test-launch")
+            .args(["--port", &rtsp_port.to_string(), TEST_PIPELINE])
+            .stdout(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("Failed to spawn `test-launch` from gst-rtsp-server: {e}"))?;
+
+        // Start rtsp2hls
+        let http_port = free_port()?;
+        let rtsp2hls = Command::new(env!("CARGO_BIN_EXE_rtsp2hls"))
+            .env("RTSP2HLS_SOURCE", format!("rtsp://127.0.0.1:{rtsp_port}/test"))
+            .env("RTSP2HLS_LISTEN", format!("127.0.0.1:{http_port}"))
+            .env("RTSP2HLS_TEMPDIR", &tempdir)
+            .spawn();
+        let rtsp2hls = match rtsp2hls {
+            Ok(rtsp2hls) => rtsp2hls,
+            Err(e) => {
+                let mut rtsp_server = rtsp_server;
+                let _ = rtsp_server.kill();
+                let _ = rtsp_server.wait();
+                return Err(e.into());
+            }
+        };
+        Ok(Self { rtsp_server, rtsp2hls, http_port, tempdir })
+    }
+
+    /// Performs a `GET` request and returns the status code and the body
+    fn get(&self, target: &str) -> Result<(u16, Vec<u8>), Box<dyn Error>> {
+        // Send the request
+        let mut stream = TcpStream::connect(("127.0.0.1", self.http_port))?;
+        stream.set_read_timeout(Some(STARTUP_TIMEOUT))?;
+        write!(stream, "GET {target} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")?;
+
+        // Read the response until the server closes the connection
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response)?;
+        let header_end = (response.windows(4).position(|window| window == b"\r\n\r\n"))
+            .ok_or_else(|| format!("Truncated response to {target}"))?;
+        let (header, body) = response.split_at(header_end);
+        let status = (String::from_utf8_lossy(header).split_whitespace().nth(1))
+            .ok_or_else(|| format!("Invalid response to {target}"))?
+            .parse()?;
+        Ok((status, body.get(4..).unwrap_or_default().to_vec()))
+    }
+
+    /// Waits until the playlist lists at least one fragment, and returns the fragment URIs
+    fn await_fragments(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let deadline = Instant::now() + STARTUP_TIMEOUT;
+        while Instant::now() < deadline {
+            // Connection errors and non-200 responses are expected while the server and the pipeline start up
+            if let Ok((200, playlist)) = self.get("/index.m3u8") {
+                let playlist = String::from_utf8(playlist)?;
+                let fragments: Vec<_> = (playlist.lines())
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(str::to_string)
+                    .collect();
+                if !fragments.is_empty() {
+                    return Ok(fragments);
+                }
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        Err(format!("No fragment has been listed within {}s", STARTUP_TIMEOUT.as_secs()).into())
+    }
+}
+impl Drop for Harness {
+    fn drop(&mut self) {
+        for child in [&mut self.rtsp2hls, &mut self.rtsp_server] {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        let _ = fs::remove_dir_all(&self.tempdir);
+    }
+}
+
+/// Gets a currently unused local TCP port
+fn free_port() -> Result<u16, Box<dyn Error>> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    Ok(listener.local_addr()?.port())
+}
+
+/// The playlist appears and lists fragments that are fetchable as MPEG-TS
+#[test]
+fn serve_playlist_and_fragments() -> Result<(), Box<dyn Error>> {
+    let harness = Harness::start()?;
+    let fragments = harness.await_fragments()?;
+
+    // Fetch the newest fragment
+    let fragment = fragments.last().ok_or("Missing fragment")?;
+    let (status, body) = harness.get(fragment)?;
+    assert_eq!(status, 200, "{fragment}");
+    assert_eq!(body.first(), Some(&0x47), "{fragment} is not an MPEG-TS stream");
+    Ok(())
+}
+
+/// The status endpoint reports the stream as live once it is being served
+#[test]
+fn report_live_status() -> Result<(), Box<dyn Error>> {
+    let harness = Harness::start()?;
+    harness.await_fragments()?;
+
+    // Query the status
+    let (status, body) = harness.get("/status")?;
+    assert_eq!(status, 200);
+    let body = String::from_utf8(body)?;
+    assert!(body.contains(r#""state":"live""#), "{body}");
+    Ok(())
+}